@@ -0,0 +1,20 @@
+//! Animates a generation in the terminal, no window needed:
+//! `cargo run --example terminal --no-default-features -- [width] [height]`
+
+use std::io;
+use std::time::Duration;
+use wfc::export::AsciiRenderer;
+use wfc::{Map, WFCState};
+
+fn main() -> anyhow::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let width = args.next().map_or(Ok(40), |arg| arg.parse())?;
+    let height = args.next().map_or(Ok(20), |arg| arg.parse())?;
+
+    let mut state = WFCState::new(Map::new(width, height)?);
+    AsciiRenderer::new().with_color(true).animate(
+        &mut state,
+        &mut io::stdout().lock(),
+        Duration::from_millis(5),
+    )
+}
@@ -1,5 +1,5 @@
 use crate::grid::Coord;
-use std::collections::HashSet;
+use serde::{Deserialize, Serialize};
 use std::fmt;
 
 #[derive(Debug)]
@@ -7,6 +7,8 @@ pub enum BucketQueueError {
     EntropyOutOfBounds { entropy: usize, max: usize },
     ZeroEntropy,
     EntryNotFound { coord: Coord },
+    CoordOutOfBounds { coord: Coord },
+    Corrupted { reason: &'static str },
 }
 
 impl fmt::Display for BucketQueueError {
@@ -19,20 +21,46 @@ impl fmt::Display for BucketQueueError {
             Self::EntryNotFound { coord } => {
                 write!(f, "no entry at {:?}", coord)
             }
+            Self::CoordOutOfBounds { coord } => {
+                write!(f, "{:?} is outside the queue's grid", coord)
+            }
+            Self::Corrupted { reason } => write!(f, "corrupted queue: {}", reason),
         }
     }
 }
 
 impl std::error::Error for BucketQueueError {}
 
+/// Where a queued cell currently lives: its bucket and its position inside it.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct Slot {
+    bucket: usize,
+    position: usize,
+}
+
+/// Priority queue of cells keyed by entropy. Every cell of the grid has a
+/// slot recording its bucket and position, so inserting, moving and removing
+/// a cell are O(1). `min_bucket` is a lower bound on the lowest non-empty
+/// bucket and only ever moves forward past empty buckets.
+#[derive(Serialize, Deserialize)]
 pub struct BucketQueue {
-    buckets: Vec<HashSet<Coord>>,
+    buckets: Vec<Vec<Coord>>,
+    slots: Vec<Option<Slot>>,
+    width: usize,
+    height: usize,
+    min_bucket: usize,
 }
 
 impl BucketQueue {
-    pub fn new(max_entropy: usize) -> Self {
-        let buckets = (0..max_entropy).map(|_| HashSet::new()).collect();
-        Self { buckets }
+    pub fn new(max_entropy: usize, width: usize, height: usize) -> Self {
+        let buckets = (0..max_entropy).map(|_| Vec::new()).collect();
+        Self {
+            buckets,
+            slots: vec![None; width * height],
+            width,
+            height,
+            min_bucket: 0,
+        }
     }
 
     fn get_bucket_index(&self, entropy: usize) -> Result<usize, BucketQueueError> {
@@ -49,9 +77,42 @@ impl BucketQueue {
         Ok(index)
     }
 
+    fn get_slot_index(&self, coord: Coord) -> Result<usize, BucketQueueError> {
+        if coord.row >= self.height || coord.col >= self.width {
+            return Err(BucketQueueError::CoordOutOfBounds { coord });
+        }
+        Ok(coord.to_index(self.width))
+    }
+
+    fn push_to_bucket(&mut self, slot_index: usize, coord: Coord, bucket: usize) {
+        self.slots[slot_index] = Some(Slot {
+            bucket,
+            position: self.buckets[bucket].len(),
+        });
+        self.buckets[bucket].push(coord);
+        self.min_bucket = self.min_bucket.min(bucket);
+    }
+
+    fn take_from_bucket(&mut self, slot: Slot) {
+        let bucket = &mut self.buckets[slot.bucket];
+        bucket.swap_remove(slot.position);
+
+        // The last entry was moved into the vacated position
+        if let Some(&moved) = bucket.get(slot.position) {
+            let moved_index = moved.to_index(self.width);
+            self.slots[moved_index] = Some(slot);
+        }
+    }
+
+    /// Inserts `coord` with the given entropy, moving it if it is already queued.
     pub fn insert(&mut self, coord: Coord, entropy: usize) -> Result<(), BucketQueueError> {
-        let index = self.get_bucket_index(entropy)?;
-        self.buckets[index].insert(coord);
+        let bucket = self.get_bucket_index(entropy)?;
+        let slot_index = self.get_slot_index(coord)?;
+
+        if let Some(slot) = self.slots[slot_index].take() {
+            self.take_from_bucket(slot);
+        }
+        self.push_to_bucket(slot_index, coord, bucket);
         Ok(())
     }
 
@@ -60,42 +121,101 @@ impl BucketQueue {
         coord: Coord,
         new_entropy: usize,
     ) -> Result<(), BucketQueueError> {
-        let new_index = self.get_bucket_index(new_entropy)?;
+        let new_bucket = self.get_bucket_index(new_entropy)?;
+        let slot_index = self.get_slot_index(coord)?;
 
-        // Find and remove from whichever bucket it's currently in
-        let removed = self.buckets.iter_mut().any(|bucket| bucket.remove(&coord));
+        let slot = self.slots[slot_index]
+            .take()
+            .ok_or(BucketQueueError::EntryNotFound { coord })?;
 
-        if !removed {
-            return Err(BucketQueueError::EntryNotFound { coord });
+        if slot.bucket == new_bucket {
+            self.slots[slot_index] = Some(slot);
+            return Ok(());
         }
 
-        self.buckets[new_index].insert(coord);
+        self.take_from_bucket(slot);
+        self.push_to_bucket(slot_index, coord, new_bucket);
         Ok(())
     }
 
+    pub fn contains(&self, coord: Coord) -> bool {
+        self.get_slot_index(coord)
+            .is_ok_and(|slot_index| self.slots[slot_index].is_some())
+    }
+
     pub fn peek_min(&self) -> Option<Coord> {
-        self.buckets
+        self.buckets[self.min_bucket..]
             .iter()
             .find(|bucket| !bucket.is_empty())?
-            .iter()
-            .next()
+            .last()
             .copied()
     }
 
     /// Returns (coord, entropy) where entropy is derived from the bucket index
     pub fn extract_min(&mut self) -> Option<(Coord, usize)> {
-        let index = self.buckets.iter().position(|bucket| !bucket.is_empty())?;
-        let coord = *self.buckets[index].iter().next()?;
-        self.buckets[index].remove(&coord);
+        while self.buckets.get(self.min_bucket)?.is_empty() {
+            self.min_bucket += 1;
+        }
+
+        let index = self.min_bucket;
+        let coord = self.buckets[index].pop()?;
+        self.slots[coord.to_index(self.width)] = None;
         Some((coord, index + 1)) // entropy = index + 1
     }
 
-    pub fn remove(&mut self, coord: Coord) -> Result<(), BucketQueueError> {
-        let removed = self.buckets.iter_mut().any(|bucket| bucket.remove(&coord));
+    /// Checks that the queue covers a `width` x `height` grid with
+    /// `max_entropy` buckets, and that its slots and buckets agree, e.g.
+    /// after loading a snapshot.
+    pub fn validate(
+        &self,
+        max_entropy: usize,
+        width: usize,
+        height: usize,
+    ) -> Result<(), BucketQueueError> {
+        let corrupted = |reason| Err(BucketQueueError::Corrupted { reason });
 
-        if !removed {
-            return Err(BucketQueueError::EntryNotFound { coord });
+        if self.width != width || self.height != height {
+            return corrupted("grid size differs from the map");
+        }
+        if width.checked_mul(height) != Some(self.slots.len()) {
+            return corrupted("slot count differs from the grid size");
         }
+        if self.buckets.len() != max_entropy {
+            return corrupted("bucket count differs from the maximum entropy");
+        }
+        if self.buckets[..self.min_bucket.min(max_entropy)]
+            .iter()
+            .any(|bucket| !bucket.is_empty())
+            || self.min_bucket > max_entropy
+        {
+            return corrupted("entries below the minimum bucket");
+        }
+
+        let mut queued = 0;
+        for (bucket, coords) in self.buckets.iter().enumerate() {
+            for (position, &coord) in coords.iter().enumerate() {
+                let slot_index = self.get_slot_index(coord)?;
+                match self.slots[slot_index] {
+                    Some(slot) if slot.bucket == bucket && slot.position == position => {}
+                    _ => return corrupted("bucket entry without a matching slot"),
+                }
+                queued += 1;
+            }
+        }
+        if self.slots.iter().flatten().count() != queued {
+            return corrupted("slot without a matching bucket entry");
+        }
+
+        Ok(())
+    }
+
+    pub fn remove(&mut self, coord: Coord) -> Result<(), BucketQueueError> {
+        let slot_index = self.get_slot_index(coord)?;
+        let slot = self.slots[slot_index]
+            .take()
+            .ok_or(BucketQueueError::EntryNotFound { coord })?;
+
+        self.take_from_bucket(slot);
         Ok(())
     }
 }
@@ -107,7 +227,7 @@ mod tests {
     #[test]
     fn test_insert_and_extract_min() {
         let max_entropy = 10;
-        let mut queue = BucketQueue::new(max_entropy);
+        let mut queue = BucketQueue::new(max_entropy, 3, 3);
 
         queue.insert(Coord::new(0, 0), 5).unwrap();
         queue.insert(Coord::new(1, 1), 3).unwrap();
@@ -125,7 +245,7 @@ mod tests {
     #[test]
     fn test_change_entropy() {
         let max_entropy = 15;
-        let mut queue = BucketQueue::new(max_entropy);
+        let mut queue = BucketQueue::new(max_entropy, 3, 3);
 
         queue.insert(Coord::new(0, 0), 5).unwrap();
         queue.insert(Coord::new(1, 1), 8).unwrap();
@@ -141,7 +261,7 @@ mod tests {
     #[test]
     fn test_extract_min_empty() {
         let max_entropy = 10;
-        let mut queue = BucketQueue::new(max_entropy);
+        let mut queue = BucketQueue::new(max_entropy, 3, 3);
 
         assert!(queue.extract_min().is_none());
 
@@ -153,14 +273,14 @@ mod tests {
 
     #[test]
     fn test_zero_entropy_error() {
-        let mut queue = BucketQueue::new(10);
+        let mut queue = BucketQueue::new(10, 3, 3);
         let result = queue.insert(Coord::new(0, 0), 0);
         assert!(matches!(result, Err(BucketQueueError::ZeroEntropy)));
     }
 
     #[test]
     fn test_entropy_out_of_bounds_error() {
-        let mut queue = BucketQueue::new(5);
+        let mut queue = BucketQueue::new(5, 3, 3);
         let result = queue.insert(Coord::new(0, 0), 10);
         assert!(matches!(
             result,
@@ -173,7 +293,7 @@ mod tests {
 
     #[test]
     fn test_entry_not_found_error() {
-        let mut queue = BucketQueue::new(10);
+        let mut queue = BucketQueue::new(10, 3, 3);
         let result = queue.update_entropy(Coord::new(0, 0), 5);
         assert!(matches!(
             result,
@@ -183,7 +303,7 @@ mod tests {
 
     #[test]
     fn test_peek_min() {
-        let mut queue = BucketQueue::new(10);
+        let mut queue = BucketQueue::new(10, 3, 3);
 
         assert!(queue.peek_min().is_none());
 
@@ -194,4 +314,80 @@ mod tests {
         assert_eq!(queue.peek_min(), Some(Coord::new(1, 1)));
         assert_eq!(queue.peek_min(), Some(Coord::new(1, 1))); // still there
     }
+
+    #[test]
+    fn test_remove_keeps_other_entries_addressable() {
+        let mut queue = BucketQueue::new(10, 3, 3);
+
+        queue.insert(Coord::new(0, 0), 4).unwrap();
+        queue.insert(Coord::new(0, 1), 4).unwrap();
+        queue.insert(Coord::new(0, 2), 4).unwrap();
+
+        // Removing the first entry moves the last one into its position
+        queue.remove(Coord::new(0, 0)).unwrap();
+        queue.update_entropy(Coord::new(0, 2), 2).unwrap();
+        queue.remove(Coord::new(0, 1)).unwrap();
+
+        assert!(matches!(
+            queue.remove(Coord::new(0, 0)),
+            Err(BucketQueueError::EntryNotFound { .. })
+        ));
+        assert_eq!(queue.extract_min(), Some((Coord::new(0, 2), 2)));
+        assert!(queue.extract_min().is_none());
+    }
+
+    #[test]
+    fn test_min_moves_back_below_extracted_entropy() {
+        let mut queue = BucketQueue::new(10, 3, 3);
+
+        queue.insert(Coord::new(0, 0), 3).unwrap();
+        queue.insert(Coord::new(1, 1), 6).unwrap();
+        queue.insert(Coord::new(2, 2), 8).unwrap();
+
+        assert_eq!(queue.extract_min(), Some((Coord::new(0, 0), 3)));
+        assert_eq!(queue.extract_min(), Some((Coord::new(1, 1), 6)));
+
+        queue.update_entropy(Coord::new(2, 2), 1).unwrap();
+        queue.insert(Coord::new(0, 0), 2).unwrap();
+
+        assert_eq!(queue.peek_min(), Some(Coord::new(2, 2)));
+        assert_eq!(queue.extract_min(), Some((Coord::new(2, 2), 1)));
+        assert_eq!(queue.extract_min(), Some((Coord::new(0, 0), 2)));
+    }
+
+    #[test]
+    fn test_insert_twice_moves_entry() {
+        let mut queue = BucketQueue::new(10, 3, 3);
+
+        queue.insert(Coord::new(1, 2), 7).unwrap();
+        queue.insert(Coord::new(1, 2), 2).unwrap();
+
+        assert_eq!(queue.extract_min(), Some((Coord::new(1, 2), 2)));
+        assert!(queue.extract_min().is_none());
+    }
+
+    #[test]
+    fn test_validate_detects_stale_slot() {
+        let mut queue = BucketQueue::new(10, 3, 3);
+        queue.insert(Coord::new(0, 0), 4).unwrap();
+        queue.insert(Coord::new(2, 1), 6).unwrap();
+        assert!(queue.validate(10, 3, 3).is_ok());
+        assert!(queue.validate(10, 4, 3).is_err());
+
+        queue.buckets[3].clear();
+        assert!(matches!(
+            queue.validate(10, 3, 3),
+            Err(BucketQueueError::Corrupted { .. })
+        ));
+    }
+
+    #[test]
+    fn test_coord_out_of_bounds_error() {
+        let mut queue = BucketQueue::new(10, 3, 3);
+        let result = queue.insert(Coord::new(3, 0), 5);
+        assert!(matches!(
+            result,
+            Err(BucketQueueError::CoordOutOfBounds { .. })
+        ));
+    }
 }
@@ -0,0 +1,191 @@
+use anyhow::{Context, Result, bail};
+use bevy::prelude::Resource;
+use serde::Deserialize;
+use std::fs;
+use std::path::PathBuf;
+
+/// Most cells the viewer will draw, each being a sprite entity.
+pub const MAX_CELLS: usize = 256 * 256;
+
+pub const USAGE: &str = "\
+Usage: wfc [OPTIONS]
+
+Options:
+  --config <PATH>       Read settings from a TOML file; flags override it
+  --width <CELLS>       Grid width (default 20)
+  --height <CELLS>      Grid height (default 20)
+  --tileset <PATH>      Tileset to solve with (default assets/tiledata.json)
+  --interval <SECONDS>  Time between drawn solver events (default 0.00001)
+  --cell-size <PIXELS>  Distance between cell centres (default 30)
+  --sprite-size <PIXELS>  Side length of each sprite (default 28)
+  --seed <SEED>         Fixed seed; a new random seed per run otherwise
+  -h, --help            Print this help
+
+Keys in the viewer:
+  R      Reload the config file and the options above, and regenerate
+  S      Regenerate with a new random seed
+  + / -  Grow or shrink the grid and regenerate
+
+The grid may have at most 65536 cells.";
+
+/// Viewer settings, read from an optional TOML file and then the command
+/// line. Field names match the TOML keys.
+#[derive(Resource, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ViewerConfig {
+    pub width: usize,
+    pub height: usize,
+    pub tileset: PathBuf,
+    /// Seconds between drawn solver events.
+    pub interval: f32,
+    pub cell_size: f32,
+    pub sprite_size: f32,
+    pub seed: Option<u64>,
+}
+
+impl Default for ViewerConfig {
+    fn default() -> Self {
+        Self {
+            width: 20,
+            height: 20,
+            tileset: PathBuf::from("assets/tiledata.json"),
+            interval: 0.00001,
+            cell_size: 30.0,
+            sprite_size: 28.0,
+            seed: None,
+        }
+    }
+}
+
+/// The command line the viewer was started with, kept so the config can be
+/// reloaded with the same overrides.
+#[derive(Resource, Debug, Clone)]
+pub struct ViewerArgs(pub Vec<String>);
+
+impl ViewerConfig {
+    /// Builds the config from `args` (without the program name): the file
+    /// named by `--config`, if any, then every other flag on top.
+    pub fn load(args: &[String]) -> Result<Self> {
+        let mut pairs = Vec::new();
+        let mut args = args.iter();
+        while let Some(flag) = args.next() {
+            let value = args
+                .next()
+                .with_context(|| format!("{} needs a value", flag))?;
+            pairs.push((flag.as_str(), value.as_str()));
+        }
+
+        let mut config = match pairs.iter().find(|(flag, _)| *flag == "--config") {
+            Some((_, path)) => {
+                let text =
+                    fs::read_to_string(path).with_context(|| format!("Failed to read {}", path))?;
+                toml::from_str(&text).with_context(|| format!("Invalid config {}", path))?
+            }
+            None => Self::default(),
+        };
+
+        for (flag, value) in pairs {
+            let invalid = || format!("Invalid value {:?} for {}", value, flag);
+            match flag {
+                "--config" => {}
+                "--width" => config.width = value.parse().with_context(invalid)?,
+                "--height" => config.height = value.parse().with_context(invalid)?,
+                "--tileset" => config.tileset = PathBuf::from(value),
+                "--interval" => config.interval = value.parse().with_context(invalid)?,
+                "--cell-size" => config.cell_size = value.parse().with_context(invalid)?,
+                "--sprite-size" => config.sprite_size = value.parse().with_context(invalid)?,
+                "--seed" => config.seed = Some(value.parse().with_context(invalid)?),
+                _ => bail!("Unknown option {}\n\n{}", flag, USAGE),
+            }
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.width == 0 || self.height == 0 {
+            bail!("The grid needs at least one cell");
+        }
+        if self
+            .width
+            .checked_mul(self.height)
+            .is_none_or(|cells| cells > MAX_CELLS)
+        {
+            bail!(
+                "A {}x{} grid is more than the {} cells the viewer draws",
+                self.width,
+                self.height,
+                MAX_CELLS
+            );
+        }
+        for (name, value) in [
+            ("interval", self.interval),
+            ("cell_size", self.cell_size),
+            ("sprite_size", self.sprite_size),
+        ] {
+            // NaN fails the comparison too
+            if !(value > 0.0 && value.is_finite()) {
+                bail!("{} must be a positive number, found {}", name, value);
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_flags_override_the_config_file() {
+        let path =
+            std::env::temp_dir().join(format!("wfc-viewer-config-{}.toml", std::process::id()));
+        fs::write(&path, "width = 40\nheight = 12\ninterval = 0.5\n").unwrap();
+
+        let config = ViewerConfig::load(&args(&[
+            "--config",
+            path.to_str().unwrap(),
+            "--height",
+            "30",
+            "--seed",
+            "7",
+        ]))
+        .unwrap();
+
+        assert_eq!(config.width, 40);
+        assert_eq!(config.height, 30);
+        assert_eq!(config.interval, 0.5);
+        assert_eq!(config.seed, Some(7));
+        assert_eq!(config.cell_size, ViewerConfig::default().cell_size);
+
+        fs::remove_file(path).unwrap();
+
+        assert!(ViewerConfig::load(&args(&["--width", "wide"])).is_err());
+        assert!(ViewerConfig::load(&args(&["--depth", "3"])).is_err());
+    }
+
+    #[test]
+    fn test_sizes_and_interval_must_be_positive_finite_and_bounded() {
+        for (flag, value) in [
+            ("--interval", "NaN"),
+            ("--interval", "inf"),
+            ("--interval", "0"),
+            ("--cell-size", "-30"),
+            ("--sprite-size", "0"),
+            ("--sprite-size", "inf"),
+            ("--width", "65537"),
+        ] {
+            assert!(
+                ViewerConfig::load(&args(&[flag, value])).is_err(),
+                "{} {}",
+                flag,
+                value
+            );
+        }
+    }
+}
@@ -0,0 +1,18 @@
+mod connectivity;
+mod global_constraint;
+mod path;
+mod symmetry;
+mod tile_count;
+mod verdict_cache;
+
+pub use connectivity::Connectivity;
+pub use global_constraint::{GlobalConstraint, Propagation};
+pub use path::{PathConstraint, PathEnd};
+pub use symmetry::Symmetry;
+pub use tile_count::{Amount, TileCount};
+
+/// Every cell of `map`, as passed to a constraint on its first call.
+#[cfg(test)]
+fn every_cell(map: &crate::grid::Map) -> Vec<crate::grid::Coord> {
+    map.iter().map(|(coord, _)| coord).collect()
+}
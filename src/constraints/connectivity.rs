@@ -0,0 +1,258 @@
+use super::verdict_cache::{VerdictCache, may_hold, must_hold};
+use super::{GlobalConstraint, Propagation};
+use crate::grid::{Coord, Domain, Map};
+use anyhow::{Result, bail};
+use std::collections::VecDeque;
+
+/// Requires walkable cells to form a single connected region, moving only
+/// between orthogonal neighbours.
+///
+/// The regions are only recomputed when a cell changes whether it may or
+/// must be walkable; other domain changes leave the last verdict standing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Connectivity {
+    pub walkable: Domain,
+    /// Cells that must be walkable and connected to each other. When empty,
+    /// every walkable cell of the map must be connected instead.
+    pub anchors: Vec<Coord>,
+    cache: VerdictCache,
+}
+
+impl Connectivity {
+    /// Every cell that ends up walkable must be reachable from every other.
+    /// Together with `TileCount::at_least(tile_type, Amount::Cells(1))`
+    /// this makes for exactly one region of `tile_type`.
+    pub fn all(walkable: Domain) -> Self {
+        Self::between(walkable, Vec::new())
+    }
+
+    /// Only `anchors` need to be walkable and reachable from each other;
+    /// other walkable cells may form separate regions.
+    pub fn between(walkable: Domain, anchors: Vec<Coord>) -> Self {
+        Self {
+            walkable,
+            anchors,
+            cache: VerdictCache::default(),
+        }
+    }
+
+    fn may_walk(&self, map: &Map, coord: Coord) -> bool {
+        may_hold(map, coord, self.walkable)
+    }
+
+    fn must_walk(&self, map: &Map, coord: Coord) -> bool {
+        must_hold(map, coord, self.walkable)
+    }
+
+    /// Labels each cell that may still be walkable with the index of its
+    /// component in the graph of such cells.
+    fn components(&self, map: &Map) -> Vec<Option<usize>> {
+        let mut component = vec![None; map.width * map.height];
+        let mut next = 0;
+
+        for (start, _) in map.iter() {
+            if component[map.index(start)].is_some() || !self.may_walk(map, start) {
+                continue;
+            }
+
+            component[map.index(start)] = Some(next);
+            let mut queue = VecDeque::from([start]);
+            while let Some(coord) = queue.pop_front() {
+                for (_, neighbour) in coord
+                    .neighbours(map.height, map.width)
+                    .into_iter()
+                    .flatten()
+                {
+                    let index = map.index(neighbour);
+                    if component[index].is_none() && self.may_walk(map, neighbour) {
+                        component[index] = Some(next);
+                        queue.push_back(neighbour);
+                    }
+                }
+            }
+            next += 1;
+        }
+
+        component
+    }
+
+    /// Checks the constraint against the whole map.
+    fn verdict(&self, map: &Map) -> Propagation {
+        let component = self.components(map);
+
+        // Cells that have to end up connected
+        let required: Vec<Coord> = if self.anchors.is_empty() {
+            map.iter()
+                .map(|(coord, _)| coord)
+                .filter(|&coord| self.must_walk(map, coord))
+                .collect()
+        } else {
+            self.anchors.clone()
+        };
+
+        let Some(&first) = required.first() else {
+            return Propagation::Restrict(Vec::new());
+        };
+        let Some(region) = component[map.index(first)] else {
+            return Propagation::Violated;
+        };
+        if required
+            .iter()
+            .any(|&coord| component[map.index(coord)] != Some(region))
+        {
+            return Propagation::Violated;
+        }
+
+        let mut restrictions: Vec<(Coord, Domain)> = Vec::new();
+        if self.anchors.is_empty() {
+            // A walkable cell outside the region could never connect to it
+            for (coord, _) in map.iter() {
+                if component[map.index(coord)].is_some_and(|other| other != region) {
+                    restrictions.push((coord, !self.walkable));
+                }
+            }
+        } else {
+            restrictions.extend(self.anchors.iter().map(|&coord| (coord, self.walkable)));
+        }
+
+        Propagation::Restrict(restrictions)
+    }
+}
+
+impl GlobalConstraint for Connectivity {
+    fn name(&self) -> String {
+        String::from("connectivity")
+    }
+
+    fn validate(&self, map: &Map) -> Result<()> {
+        if let Some(anchor) = self.anchors.iter().find(|&&anchor| !map.contains(anchor)) {
+            bail!(
+                "Connectivity anchor ({}, {}) is outside the {}x{} map",
+                anchor.row,
+                anchor.col,
+                map.width,
+                map.height
+            );
+        }
+        Ok(())
+    }
+
+    fn propagate(&mut self, map: &Map, changed: &[Coord]) -> Propagation {
+        if let Some(last) = self.cache.lookup(map, changed, self.walkable) {
+            return last;
+        }
+        let propagation = self.verdict(map);
+        self.cache.store(&propagation);
+        propagation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraints::every_cell;
+    use crate::constraints::{Amount, TileCount};
+    use crate::grid::TileType;
+    use crate::wfc::WFCState;
+
+    fn water() -> Domain {
+        TileType::DeepWater.mask() | TileType::ShallowWater.mask()
+    }
+
+    #[test]
+    fn test_separated_walkable_cells_violate() {
+        let mut map = Map::new(3, 1).expect("tile data should load");
+        map.get_tile_mut(Coord::new(0, 0))
+            .reset_domain_to(TileType::Grass.mask());
+        map.get_tile_mut(Coord::new(0, 1)).reset_domain_to(water());
+        map.get_tile_mut(Coord::new(0, 2))
+            .reset_domain_to(TileType::Grass.mask());
+
+        let walkable = !water();
+        let cells = every_cell(&map);
+        assert_eq!(
+            Connectivity::all(walkable).propagate(&map, &cells),
+            Propagation::Violated
+        );
+
+        let anchors = vec![Coord::new(0, 0)];
+        assert_eq!(
+            Connectivity::between(walkable, anchors).propagate(&map, &cells),
+            Propagation::Restrict(vec![(Coord::new(0, 0), walkable)])
+        );
+    }
+
+    #[test]
+    fn test_cells_cut_off_from_the_region_cannot_be_walkable() {
+        let mut map = Map::new(3, 1).expect("tile data should load");
+        map.get_tile_mut(Coord::new(0, 0))
+            .reset_domain_to(TileType::Grass.mask());
+        map.get_tile_mut(Coord::new(0, 1)).reset_domain_to(water());
+
+        let walkable = !water();
+        let mut connectivity = Connectivity::all(walkable);
+        assert_eq!(
+            connectivity.propagate(&map, &every_cell(&map)),
+            Propagation::Restrict(vec![(Coord::new(0, 2), !walkable)])
+        );
+
+        // Narrowing a cell without changing its walkability keeps the verdict
+        map.get_tile_mut(Coord::new(0, 1))
+            .reset_domain_to(TileType::DeepWater.mask());
+        map.get_tile_mut(Coord::new(0, 2))
+            .reset_domain_to(TileType::Grass.mask());
+        assert_eq!(
+            connectivity.propagate(&map, &[Coord::new(0, 1)]),
+            Propagation::Restrict(vec![(Coord::new(0, 2), !walkable)])
+        );
+        assert_eq!(
+            connectivity.propagate(&map, &[Coord::new(0, 2)]),
+            Propagation::Violated
+        );
+    }
+
+    #[test]
+    fn test_anchors_outside_the_map_are_rejected() {
+        let mut state = WFCState::new(Map::new(4, 4).expect("tile data should load"));
+        let anchors = vec![Coord::new(0, 0), Coord::new(2, 4)];
+
+        assert!(
+            state
+                .add_constraint(Connectivity::between(!water(), anchors))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_solved_land_is_one_region() {
+        let land = !(water() | TileType::River.mask());
+        let mut state = WFCState::with_seed(Map::new(12, 12).expect("tile data should load"), 1);
+        state.add_constraint(Connectivity::all(land)).unwrap();
+        state.solve().unwrap();
+
+        let map = state.get_map();
+        let constraint = Connectivity::all(land);
+        let regions: Vec<usize> = constraint.components(map).into_iter().flatten().collect();
+        assert!(!regions.is_empty());
+        assert!(regions.iter().all(|&region| region == regions[0]));
+    }
+
+    #[test]
+    fn test_exactly_one_snow_region() {
+        let snow = TileType::Snow.mask();
+        let mut state = WFCState::with_seed(Map::new(12, 12).expect("tile data should load"), 1);
+        state.add_constraint(Connectivity::all(snow)).unwrap();
+        state
+            .add_constraint(TileCount::at_least(TileType::Snow, Amount::Cells(1)))
+            .unwrap();
+        state.solve().unwrap();
+
+        let regions: Vec<usize> = Connectivity::all(snow)
+            .components(state.get_map())
+            .into_iter()
+            .flatten()
+            .collect();
+        assert!(!regions.is_empty());
+        assert!(regions.iter().all(|&region| region == regions[0]));
+    }
+}
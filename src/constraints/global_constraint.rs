@@ -0,0 +1,33 @@
+use crate::grid::{Coord, Domain, Map};
+use anyhow::Result;
+
+/// What a global constraint concluded from the current domains.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Propagation {
+    /// No completion of the map can satisfy the constraint any more.
+    Violated,
+    /// The constraint still holds. Each entry narrows a cell to the tiles
+    /// the constraint allows there; an empty list changes nothing.
+    Restrict(Vec<(Coord, Domain)>),
+}
+
+/// A rule over the whole map rather than between neighbours, such as tile
+/// counts or connectivity. The solver consults every constraint each time
+/// adjacency propagation settles, applies the restrictions it returns, and
+/// backtracks when one is violated.
+pub trait GlobalConstraint: Send + Sync {
+    /// Short description used in errors and events.
+    fn name(&self) -> String;
+
+    /// Checks the constraint's own settings against `map` when it is added
+    /// to a solver, e.g. that the cells it names lie inside the map.
+    fn validate(&self, _map: &Map) -> Result<()> {
+        Ok(())
+    }
+
+    /// `changed` lists the cells whose domains may have changed since the
+    /// previous call, possibly more than once. On the first call it lists
+    /// every cell of the map. Constraints keep what they learned between
+    /// calls and only look at these cells instead of rescanning the map.
+    fn propagate(&mut self, map: &Map, changed: &[Coord]) -> Propagation;
+}
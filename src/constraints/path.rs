@@ -0,0 +1,210 @@
+use super::verdict_cache::{VerdictCache, may_hold};
+use super::{GlobalConstraint, Propagation};
+use crate::grid::{Coord, Domain, Map};
+use anyhow::{Result, bail};
+use std::collections::VecDeque;
+
+/// Where a `PathConstraint` has to lead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathEnd {
+    Cell(Coord),
+    /// Any cell on the edge of the map.
+    Border,
+}
+
+/// Requires an unbroken, orthogonally connected run of `tiles` from `from`
+/// to `to`, e.g. a river flowing from a spring out of the map.
+///
+/// Reachability is only rechecked when a cell changes whether it may or
+/// must be a path tile; other domain changes leave the last verdict
+/// standing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PathConstraint {
+    pub tiles: Domain,
+    pub from: Coord,
+    pub to: PathEnd,
+    cache: VerdictCache,
+}
+
+impl PathConstraint {
+    pub fn new(tiles: Domain, from: Coord, to: PathEnd) -> Self {
+        Self {
+            tiles,
+            from,
+            to,
+            cache: VerdictCache::default(),
+        }
+    }
+
+    fn may_be_path(&self, map: &Map, coord: Coord) -> bool {
+        may_hold(map, coord, self.tiles)
+    }
+
+    fn is_end(&self, map: &Map, coord: Coord) -> bool {
+        match self.to {
+            PathEnd::Cell(end) => coord == end,
+            PathEnd::Border => {
+                coord.row == 0
+                    || coord.col == 0
+                    || coord.row + 1 == map.height
+                    || coord.col + 1 == map.width
+            }
+        }
+    }
+
+    /// Whether the end can still be reached from `from` through cells that
+    /// may become path tiles.
+    fn reachable(&self, map: &Map) -> bool {
+        if !self.may_be_path(map, self.from) {
+            return false;
+        }
+
+        let mut visited = vec![false; map.width * map.height];
+        visited[map.index(self.from)] = true;
+        let mut queue = VecDeque::from([self.from]);
+
+        while let Some(coord) = queue.pop_front() {
+            if self.is_end(map, coord) {
+                return true;
+            }
+
+            for (_, neighbour) in coord
+                .neighbours(map.height, map.width)
+                .into_iter()
+                .flatten()
+            {
+                let index = map.index(neighbour);
+                if !visited[index] && self.may_be_path(map, neighbour) {
+                    visited[index] = true;
+                    queue.push_back(neighbour);
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Checks the constraint against the whole map.
+    fn verdict(&self, map: &Map) -> Propagation {
+        if !self.reachable(map) {
+            return Propagation::Violated;
+        }
+
+        let mut restrictions = vec![(self.from, self.tiles)];
+        if let PathEnd::Cell(end) = self.to {
+            restrictions.push((end, self.tiles));
+        }
+        Propagation::Restrict(restrictions)
+    }
+}
+
+impl GlobalConstraint for PathConstraint {
+    fn name(&self) -> String {
+        match self.to {
+            PathEnd::Cell(end) => format!(
+                "path from ({}, {}) to ({}, {})",
+                self.from.row, self.from.col, end.row, end.col
+            ),
+            PathEnd::Border => format!(
+                "path from ({}, {}) to the border",
+                self.from.row, self.from.col
+            ),
+        }
+    }
+
+    fn validate(&self, map: &Map) -> Result<()> {
+        let ends = match self.to {
+            PathEnd::Cell(end) => vec![self.from, end],
+            PathEnd::Border => vec![self.from],
+        };
+        if let Some(end) = ends.into_iter().find(|&end| !map.contains(end)) {
+            bail!(
+                "{} leaves the {}x{} map at ({}, {})",
+                self.name(),
+                map.width,
+                map.height,
+                end.row,
+                end.col
+            );
+        }
+        Ok(())
+    }
+
+    fn propagate(&mut self, map: &Map, changed: &[Coord]) -> Propagation {
+        if let Some(last) = self.cache.lookup(map, changed, self.tiles) {
+            return last;
+        }
+        let propagation = self.verdict(map);
+        self.cache.store(&propagation);
+        propagation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::TileType;
+    use crate::wfc::WFCState;
+
+    #[test]
+    fn test_blocked_path_is_violated() {
+        let mut map = Map::new(3, 3).expect("tile data should load");
+        for row in 0..3 {
+            map.get_tile_mut(Coord::new(row, 1))
+                .reset_domain_to(TileType::Snow.mask());
+        }
+
+        let river = TileType::River.mask();
+        let cells: Vec<Coord> = map.iter().map(|(coord, _)| coord).collect();
+        let mut across =
+            PathConstraint::new(river, Coord::new(0, 0), PathEnd::Cell(Coord::new(2, 2)));
+        assert_eq!(across.propagate(&map, &cells), Propagation::Violated);
+
+        let mut out = PathConstraint::new(river, Coord::new(1, 0), PathEnd::Border);
+        assert!(matches!(
+            out.propagate(&map, &cells),
+            Propagation::Restrict(_)
+        ));
+    }
+
+    #[test]
+    fn test_ends_outside_the_map_are_rejected() {
+        let river = TileType::River.mask();
+        let mut state = WFCState::new(Map::new(5, 3).expect("tile data should load"));
+
+        let outside = Coord::new(3, 0);
+        for path in [
+            PathConstraint::new(river, outside, PathEnd::Border),
+            PathConstraint::new(river, Coord::new(0, 0), PathEnd::Cell(outside)),
+        ] {
+            assert!(state.add_constraint(path).is_err());
+        }
+        assert!(
+            state
+                .add_constraint(PathConstraint::new(
+                    river,
+                    Coord::new(2, 4),
+                    PathEnd::Border
+                ))
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn test_solved_map_contains_the_river() {
+        let river = TileType::River.mask();
+        let from = Coord::new(1, 1);
+        let to = Coord::new(8, 9);
+
+        let mut state = WFCState::with_seed(Map::new(10, 10).expect("tile data should load"), 3);
+        state
+            .add_constraint(PathConstraint::new(river, from, PathEnd::Cell(to)))
+            .unwrap();
+        state.solve().unwrap();
+
+        let map = state.get_map();
+        assert_eq!(map.get_tile(from).tile_type(), Some(TileType::River));
+        assert_eq!(map.get_tile(to).tile_type(), Some(TileType::River));
+        assert!(PathConstraint::new(river, from, PathEnd::Cell(to)).reachable(map));
+    }
+}
@@ -0,0 +1,228 @@
+use super::{GlobalConstraint, Propagation};
+use crate::grid::{Coord, Domain, Map, TileData, TileType};
+use anyhow::{Result, bail};
+
+/// Keeps the map symmetric: every cell ends up with the mirrored or
+/// rotated counterpart of the tile at its partner cells, as declared in the
+/// tileset. Collapsing a cell collapses its partners through propagation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Symmetry {
+    /// Mirrored across the vertical centre line.
+    LeftRight,
+    /// Mirrored across the horizontal centre line.
+    TopBottom,
+    /// Mirrored across the main diagonal. Needs a square map.
+    Diagonal,
+    /// Unchanged by a half turn.
+    HalfTurn,
+    /// Unchanged by a quarter turn. Needs a square map.
+    QuarterTurn,
+}
+
+/// A map transformation: an optional left-right mirror followed by a
+/// number of clockwise quarter turns.
+#[derive(Debug, Clone, Copy)]
+struct Transform {
+    mirror: bool,
+    quarter_turns: u8,
+}
+
+impl Transform {
+    fn tile(self, tile_data: &TileData, tile_type: TileType) -> TileType {
+        let mut tile_type = if self.mirror {
+            tile_data.mirrored(tile_type)
+        } else {
+            tile_type
+        };
+        for _ in 0..self.quarter_turns {
+            tile_type = tile_data.rotated(tile_type);
+        }
+        tile_type
+    }
+
+    fn domain(self, tile_data: &TileData, domain: Domain) -> Domain {
+        domain
+            .iter_tiles()
+            .fold(Domain::empty(), |moved, tile_type| {
+                moved | self.tile(tile_data, tile_type).mask()
+            })
+    }
+
+    /// Where the cell at `coord` of a `width` x `height` map ends up.
+    fn coord(self, coord: Coord, width: usize, height: usize) -> Coord {
+        let (mut row, mut col) = (coord.row, coord.col);
+        let (mut width, mut height) = (width, height);
+        if self.mirror {
+            col = width - 1 - col;
+        }
+        for _ in 0..self.quarter_turns {
+            (row, col) = (col, height - 1 - row);
+            (width, height) = (height, width);
+        }
+        Coord::new(row, col)
+    }
+}
+
+impl Symmetry {
+    /// The transformations other than the identity that map the symmetric
+    /// map onto itself.
+    fn transforms(self) -> &'static [Transform] {
+        match self {
+            Symmetry::LeftRight => &[Transform {
+                mirror: true,
+                quarter_turns: 0,
+            }],
+            Symmetry::TopBottom => &[Transform {
+                mirror: true,
+                quarter_turns: 2,
+            }],
+            Symmetry::Diagonal => &[Transform {
+                mirror: true,
+                quarter_turns: 3,
+            }],
+            Symmetry::HalfTurn => &[Transform {
+                mirror: false,
+                quarter_turns: 2,
+            }],
+            Symmetry::QuarterTurn => &[
+                Transform {
+                    mirror: false,
+                    quarter_turns: 1,
+                },
+                Transform {
+                    mirror: false,
+                    quarter_turns: 2,
+                },
+                Transform {
+                    mirror: false,
+                    quarter_turns: 3,
+                },
+            ],
+        }
+    }
+
+    fn needs_square(self) -> bool {
+        matches!(self, Symmetry::Diagonal | Symmetry::QuarterTurn)
+    }
+
+    /// The cell and tile that `coord` holding `tile_type` implies under
+    /// each of the symmetry's transformations, in a `width` x `height` map
+    /// using the counterparts of `tile_data`.
+    pub fn partners(
+        self,
+        coord: Coord,
+        tile_type: TileType,
+        tile_data: &TileData,
+        width: usize,
+        height: usize,
+    ) -> impl Iterator<Item = (Coord, TileType)> + '_ {
+        self.transforms().iter().map(move |transform| {
+            (
+                transform.coord(coord, width, height),
+                transform.tile(tile_data, tile_type),
+            )
+        })
+    }
+}
+
+impl GlobalConstraint for Symmetry {
+    fn name(&self) -> String {
+        format!("{:?} symmetry", self)
+    }
+
+    fn validate(&self, map: &Map) -> Result<()> {
+        if self.needs_square() && map.width != map.height {
+            bail!(
+                "{} needs a square map, not {}x{}",
+                self.name(),
+                map.width,
+                map.height
+            );
+        }
+        Ok(())
+    }
+
+    /// Only the changed cells need checking: the transformations form a
+    /// group, so a cell restricted by a partner is itself a partner of it
+    /// and shows up as changed once it is.
+    fn propagate(&mut self, map: &Map, changed: &[Coord]) -> Propagation {
+        if self.needs_square() && map.width != map.height {
+            return Propagation::Violated;
+        }
+
+        let mut restrictions = Vec::new();
+        for &coord in changed {
+            let domain = map.get_tile(coord).current_domain;
+            for &transform in self.transforms() {
+                let partner = transform.coord(coord, map.width, map.height);
+                let allowed = transform.domain(&map.tile_data, domain);
+
+                let current = map.get_tile(partner).current_domain;
+                if current.intersection(allowed) != current {
+                    restrictions.push((partner, allowed));
+                }
+            }
+        }
+
+        Propagation::Restrict(restrictions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wfc::WFCState;
+
+    #[test]
+    fn test_solved_maps_are_symmetric() {
+        let symmetries = [
+            Symmetry::LeftRight,
+            Symmetry::TopBottom,
+            Symmetry::Diagonal,
+            Symmetry::HalfTurn,
+            Symmetry::QuarterTurn,
+        ];
+
+        for (seed, symmetry) in symmetries.into_iter().enumerate() {
+            let map = Map::new(11, 11).expect("tile data should load");
+            let mut state = WFCState::with_seed(map, seed as u64);
+            state.add_constraint(symmetry).unwrap();
+            state.solve().unwrap();
+
+            let map = state.get_map();
+            for (coord, tile) in map.iter() {
+                let tile_type = tile.tile_type().unwrap();
+                let partners =
+                    symmetry.partners(coord, tile_type, &map.tile_data, map.width, map.height);
+                for (partner, expected) in partners {
+                    assert_eq!(
+                        map.get_tile(partner).tile_type(),
+                        Some(expected),
+                        "{:?}: {:?} and {:?}",
+                        symmetry,
+                        coord,
+                        partner
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_quarter_turn_needs_a_square_map() {
+        let map = Map::new(6, 4).expect("tile data should load");
+        assert!(matches!(
+            Symmetry::QuarterTurn.propagate(&map, &[]),
+            Propagation::Violated
+        ));
+        assert!(matches!(
+            Symmetry::HalfTurn.propagate(&map, &[]),
+            Propagation::Restrict(_)
+        ));
+
+        let mut state = WFCState::with_seed(map, 0);
+        assert!(state.add_constraint(Symmetry::Diagonal).is_err());
+        assert!(state.add_constraint(Symmetry::QuarterTurn).is_err());
+        assert!(state.add_constraint(Symmetry::HalfTurn).is_ok());
+    }
+}
@@ -0,0 +1,238 @@
+use super::{GlobalConstraint, Propagation};
+use crate::grid::{Coord, Map, Tile, TileType};
+
+/// A number of cells, either absolute or as a fraction of the map.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Amount {
+    Cells(usize),
+    Fraction(f64),
+}
+
+/// Bounds how many cells of the map may end up as `tile_type`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileCount {
+    pub tile_type: TileType,
+    pub min: Option<Amount>,
+    pub max: Option<Amount>,
+    /// How each cell was counted when it was last seen.
+    counted: Vec<Counted>,
+    fixed: usize,
+    open: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Counted {
+    /// Can no longer be `tile_type`.
+    Excluded,
+    /// Can still be `tile_type` or something else.
+    Open,
+    /// Collapsed to `tile_type`.
+    Fixed,
+}
+
+impl TileCount {
+    pub fn at_least(tile_type: TileType, min: Amount) -> Self {
+        Self {
+            tile_type,
+            min: Some(min),
+            max: None,
+            counted: Vec::new(),
+            fixed: 0,
+            open: 0,
+        }
+    }
+
+    pub fn at_most(tile_type: TileType, max: Amount) -> Self {
+        Self {
+            tile_type,
+            min: None,
+            max: Some(max),
+            counted: Vec::new(),
+            fixed: 0,
+            open: 0,
+        }
+    }
+
+    pub fn between(tile_type: TileType, min: Amount, max: Amount) -> Self {
+        Self {
+            tile_type,
+            min: Some(min),
+            max: Some(max),
+            counted: Vec::new(),
+            fixed: 0,
+            open: 0,
+        }
+    }
+
+    /// The bounds in cells for a map of `total` cells. Fractions round
+    /// towards the allowed range: the minimum up, the maximum down.
+    fn bounds(&self, total: usize) -> (usize, usize) {
+        let min = match self.min {
+            Some(Amount::Cells(cells)) => cells,
+            Some(Amount::Fraction(fraction)) => (fraction * total as f64).ceil() as usize,
+            None => 0,
+        };
+        let max = match self.max {
+            Some(Amount::Cells(cells)) => cells,
+            Some(Amount::Fraction(fraction)) => (fraction * total as f64).floor() as usize,
+            None => total,
+        };
+        (min, max)
+    }
+
+    fn classify(&self, tile: &Tile) -> Counted {
+        if tile
+            .current_domain
+            .intersection(self.tile_type.mask())
+            .is_empty()
+        {
+            Counted::Excluded
+        } else if tile.is_collapsed() {
+            Counted::Fixed
+        } else {
+            Counted::Open
+        }
+    }
+
+    /// Moves the cells in `changed` to their current category, keeping the
+    /// totals in step.
+    fn recount(&mut self, map: &Map, changed: &[Coord]) {
+        let total = map.width * map.height;
+        if self.counted.len() != total {
+            self.counted = vec![Counted::Excluded; total];
+            self.fixed = 0;
+            self.open = 0;
+        }
+
+        for &coord in changed {
+            let index = map.index(coord);
+            let now = self.classify(map.get_tile(coord));
+            let before = std::mem::replace(&mut self.counted[index], now);
+
+            match before {
+                Counted::Excluded => {}
+                Counted::Open => self.open -= 1,
+                Counted::Fixed => self.fixed -= 1,
+            }
+            match now {
+                Counted::Excluded => {}
+                Counted::Open => self.open += 1,
+                Counted::Fixed => self.fixed += 1,
+            }
+        }
+    }
+
+    fn open_cells(&self, map: &Map) -> impl Iterator<Item = Coord> + '_ {
+        let width = map.width;
+        self.counted
+            .iter()
+            .enumerate()
+            .filter(|(_, counted)| **counted == Counted::Open)
+            .map(move |(index, _)| Coord::from_index(index, width))
+    }
+}
+
+impl GlobalConstraint for TileCount {
+    fn name(&self) -> String {
+        format!("count of {}", self.tile_type.name())
+    }
+
+    fn propagate(&mut self, map: &Map, changed: &[Coord]) -> Propagation {
+        self.recount(map, changed);
+        let (min, max) = self.bounds(map.width * map.height);
+        let (fixed, open) = (self.fixed, self.open);
+
+        if fixed > max || fixed + open < min {
+            return Propagation::Violated;
+        }
+        if open == 0 {
+            return Propagation::Restrict(Vec::new());
+        }
+
+        // At either bound, the undecided cells have no choice left
+        let mask = self.tile_type.mask();
+        if fixed == max {
+            return Propagation::Restrict(
+                self.open_cells(map).map(|coord| (coord, !mask)).collect(),
+            );
+        }
+        if fixed + open == min {
+            return Propagation::Restrict(
+                self.open_cells(map).map(|coord| (coord, mask)).collect(),
+            );
+        }
+        Propagation::Restrict(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraints::every_cell;
+
+    #[test]
+    fn test_bounds_round_fractions_inwards() {
+        let count = TileCount::between(
+            TileType::Desert,
+            Amount::Fraction(0.05),
+            Amount::Fraction(0.1),
+        );
+        assert_eq!(count.bounds(25), (2, 2));
+        assert_eq!(count.bounds(100), (5, 10));
+    }
+
+    #[test]
+    fn test_propagate_prunes_at_the_bounds() {
+        let mut map = Map::new(3, 1).expect("tile data should load");
+        map.get_tile_mut(Coord::new(0, 0))
+            .reset_domain_to(TileType::Snow.mask());
+
+        let cells = every_cell(&map);
+
+        let mut at_most_one = TileCount::at_most(TileType::Snow, Amount::Cells(1));
+        assert_eq!(
+            at_most_one.propagate(&map, &cells),
+            Propagation::Restrict(vec![
+                (Coord::new(0, 1), !TileType::Snow.mask()),
+                (Coord::new(0, 2), !TileType::Snow.mask()),
+            ])
+        );
+
+        let mut at_least_three = TileCount::at_least(TileType::Snow, Amount::Cells(3));
+        assert!(
+            matches!(at_least_three.propagate(&map, &cells), Propagation::Restrict(cells) if cells.len() == 2)
+        );
+
+        let mut none = TileCount::at_most(TileType::Snow, Amount::Cells(0));
+        assert_eq!(none.propagate(&map, &cells), Propagation::Violated);
+    }
+
+    #[test]
+    fn test_propagate_only_recounts_changed_cells() {
+        let mut map = Map::new(3, 1).expect("tile data should load");
+        let mut at_most_one = TileCount::at_most(TileType::Snow, Amount::Cells(1));
+        assert_eq!(
+            at_most_one.propagate(&map, &every_cell(&map)),
+            Propagation::Restrict(Vec::new())
+        );
+
+        let snow = Coord::new(0, 2);
+        map.get_tile_mut(snow)
+            .reset_domain_to(TileType::Snow.mask());
+        assert_eq!(
+            at_most_one.propagate(&map, &[snow, snow]),
+            Propagation::Restrict(vec![
+                (Coord::new(0, 0), !TileType::Snow.mask()),
+                (Coord::new(0, 1), !TileType::Snow.mask()),
+            ])
+        );
+
+        // Undone again, as when backtracking
+        let every_tile = map.tile_data.tiles;
+        map.get_tile_mut(snow).reset_domain_to(every_tile);
+        assert_eq!(
+            at_most_one.propagate(&map, &[snow]),
+            Propagation::Restrict(Vec::new())
+        );
+    }
+}
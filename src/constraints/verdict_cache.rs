@@ -0,0 +1,58 @@
+use super::Propagation;
+use crate::grid::{Coord, Domain, Map};
+
+/// Remembers the verdict of a constraint that only depends on which cells
+/// may and which must hold one of a set of tiles, so that other domain
+/// changes can skip the recheck.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(super) struct VerdictCache {
+    /// Whether each cell may and must hold one of the tiles, as last seen.
+    seen: Vec<(bool, bool)>,
+    /// The verdict for `seen`, which holds until one of them changes.
+    last: Option<Propagation>,
+}
+
+impl VerdictCache {
+    /// Records which of the `changed` cells may and must hold one of
+    /// `tiles` and returns the last verdict if none of it differs from
+    /// what was seen before.
+    pub(super) fn lookup(
+        &mut self,
+        map: &Map,
+        changed: &[Coord],
+        tiles: Domain,
+    ) -> Option<Propagation> {
+        let total = map.width * map.height;
+        let mut differs = self.seen.len() != total;
+        if differs {
+            self.seen = vec![(false, false); total];
+        }
+
+        for &coord in changed {
+            let now = (may_hold(map, coord, tiles), must_hold(map, coord, tiles));
+            let before = std::mem::replace(&mut self.seen[map.index(coord)], now);
+            differs |= before != now;
+        }
+
+        if differs { None } else { self.last.clone() }
+    }
+
+    /// Keeps `verdict` until the next change.
+    pub(super) fn store(&mut self, verdict: &Propagation) {
+        self.last = Some(verdict.clone());
+    }
+}
+
+/// Whether the cell at `coord` can still become one of `tiles`.
+pub(super) fn may_hold(map: &Map, coord: Coord, tiles: Domain) -> bool {
+    !map.get_tile(coord)
+        .current_domain
+        .intersection(tiles)
+        .is_empty()
+}
+
+/// Whether the cell at `coord` is bound to become one of `tiles`.
+pub(super) fn must_hold(map: &Map, coord: Coord, tiles: Domain) -> bool {
+    let domain = map.get_tile(coord).current_domain;
+    !domain.is_empty() && domain.difference(tiles).is_empty()
+}
@@ -0,0 +1,7 @@
+mod ascii;
+mod png;
+mod tiled;
+
+pub use ascii::{AsciiRenderer, AsciiStyle};
+pub use png::{PngExporter, PngOptions};
+pub use tiled::{TiledMap, TiledOptions, export_tiled};
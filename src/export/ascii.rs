@@ -0,0 +1,252 @@
+use crate::grid::{Map, TileType};
+use crate::wfc::{VisualEvent, WFCState};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::Write;
+use std::thread;
+use std::time::Duration;
+
+const RESET: &str = "\x1b[0m";
+
+/// How a collapsed tile is drawn: a character and an optional ANSI
+/// 256-colour foreground.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AsciiStyle {
+    pub glyph: char,
+    pub color: Option<u8>,
+}
+
+impl AsciiStyle {
+    pub fn new(glyph: char, color: Option<u8>) -> Self {
+        Self { glyph, color }
+    }
+}
+
+/// Renders maps as text, one character per cell. Uncollapsed cells show
+/// their entropy as a base-36 digit (`2`-`9`, then `a` onwards), and cells with
+/// an empty domain show `0`.
+#[derive(Debug, Clone)]
+pub struct AsciiRenderer {
+    styles: HashMap<TileType, AsciiStyle>,
+    color: bool,
+}
+
+impl Default for AsciiRenderer {
+    fn default() -> Self {
+        let styles = TileType::ALL
+            .into_iter()
+            .map(|tile_type| (tile_type, default_style(tile_type)))
+            .collect();
+
+        Self {
+            styles,
+            color: false,
+        }
+    }
+}
+
+fn default_style(tile_type: TileType) -> AsciiStyle {
+    let (glyph, color) = match tile_type {
+        // Water types
+        TileType::DeepWater => ('~', 18),
+        TileType::ShallowWater => ('~', 39),
+        TileType::River => ('=', 33),
+
+        // Land types
+        TileType::Beach => ('.', 229),
+        TileType::Grass => ('"', 70),
+        TileType::Forest => ('T', 22),
+        TileType::Mountain => ('^', 244),
+        TileType::Snow => ('*', 255),
+        TileType::Desert => (':', 214),
+
+        // Transitions are drawn as the side the second terrain is on, in
+        // the colour of their family
+        TileType::BeachWaterN => ('n', 187),
+        TileType::BeachWaterE => ('e', 187),
+        TileType::BeachWaterS => ('s', 187),
+        TileType::BeachWaterW => ('w', 187),
+        TileType::BeachWaterNe => ('/', 187),
+        TileType::BeachWaterNw => ('\\', 187),
+        TileType::BeachWaterSe => ('\\', 187),
+        TileType::BeachWaterSw => ('/', 187),
+        TileType::GrassForestN => ('n', 28),
+        TileType::GrassForestE => ('e', 28),
+        TileType::GrassForestS => ('s', 28),
+        TileType::GrassForestW => ('w', 28),
+        TileType::MountainSnowN => ('n', 250),
+        TileType::MountainSnowE => ('e', 250),
+        TileType::MountainSnowS => ('s', 250),
+        TileType::MountainSnowW => ('w', 250),
+
+        // Decorations
+        TileType::Bare => (' ', 0),
+        TileType::Tree => ('t', 28),
+        TileType::Rock => ('o', 247),
+        TileType::House => ('H', 130),
+        TileType::Reeds => ('|', 106),
+    };
+
+    AsciiStyle::new(glyph, Some(color))
+}
+
+impl AsciiRenderer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_style(mut self, tile_type: TileType, style: AsciiStyle) -> Self {
+        self.styles.insert(tile_type, style);
+        self
+    }
+
+    /// Whether to emit the styles' ANSI colours. Off by default.
+    pub fn with_color(mut self, color: bool) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn render(&self, map: &Map) -> String {
+        let mut text = String::with_capacity((map.width + 1) * map.height);
+
+        for (coord, tile) in map.iter() {
+            match tile.tile_type() {
+                Some(tile_type) => self.push_tile(&mut text, tile_type),
+                None => {
+                    let entropy = tile.get_current_domain_size() as u32;
+                    text.push(char::from_digit(entropy, 36).unwrap_or('?'));
+                }
+            }
+
+            if coord.col + 1 == map.width {
+                text.push('\n');
+            }
+        }
+
+        text
+    }
+
+    fn push_tile(&self, text: &mut String, tile_type: TileType) {
+        let style = self.styles[&tile_type];
+        match style.color.filter(|_| self.color) {
+            Some(color) => {
+                let _ = write!(text, "\x1b[38;5;{}m{}{}", color, style.glyph, RESET);
+            }
+            None => text.push(style.glyph),
+        }
+    }
+
+    /// Steps `state` through its visual events, redrawing the map in place
+    /// after each one and pausing `delay` between frames.
+    ///
+    /// The solver runs ahead of its events, so the frames are drawn from a
+    /// display buffer that applies one event at a time, like the Bevy view.
+    /// Open cells are drawn blank.
+    pub fn animate(
+        &self,
+        state: &mut WFCState,
+        out: &mut impl Write,
+        delay: Duration,
+    ) -> Result<()> {
+        let width = state.get_map().width;
+        let mut cells: Vec<Option<TileType>> = state
+            .get_map()
+            .tiles()
+            .iter()
+            .map(|tile| tile.tile_type())
+            .collect();
+
+        // Clear the screen once, then only move the cursor home
+        write!(out, "\x1b[2J")?;
+
+        loop {
+            write!(out, "\x1b[H{}", self.render_cells(width, &cells))?;
+            out.flush()?;
+
+            let Some(event) = state.next() else {
+                break;
+            };
+            match event {
+                VisualEvent::SetTile { tile_type, coord } => {
+                    cells[coord.to_index(width)] = Some(tile_type);
+                }
+                VisualEvent::UndoTile { coord } => cells[coord.to_index(width)] = None,
+            }
+            thread::sleep(delay);
+        }
+
+        Ok(())
+    }
+
+    fn render_cells(&self, width: usize, cells: &[Option<TileType>]) -> String {
+        let mut text = String::with_capacity(cells.len() + cells.len() / width.max(1));
+
+        for row in cells.chunks(width.max(1)) {
+            for cell in row {
+                match *cell {
+                    Some(tile_type) => self.push_tile(&mut text, tile_type),
+                    None => text.push(' '),
+                }
+            }
+            text.push('\n');
+        }
+
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::{Coord, Domain};
+
+    #[test]
+    fn test_render_shows_glyphs_and_entropy() {
+        let mut map = Map::new(3, 2).expect("tile data should load");
+        map.get_tile_mut(Coord::new(0, 1))
+            .reset_domain_to(TileType::Forest.mask());
+        map.get_tile_mut(Coord::new(1, 0))
+            .reset_domain_to(TileType::Grass.mask() | TileType::Snow.mask());
+        map.get_tile_mut(Coord::new(1, 2))
+            .reset_domain_to(Domain::empty());
+
+        let renderer = AsciiRenderer::new();
+        assert_eq!(renderer.render(&map), "pTp\n2p0\n");
+
+        let custom = renderer.with_style(TileType::Forest, AsciiStyle::new('F', Some(22)));
+        assert_eq!(custom.render(&map), "pFp\n2p0\n");
+        assert!(
+            custom
+                .with_color(true)
+                .render(&map)
+                .contains("\x1b[38;5;22mF\x1b[0m")
+        );
+    }
+
+    #[test]
+    fn test_animate_draws_one_event_per_frame() {
+        let map = Map::new(4, 3).expect("tile data should load");
+        let mut state = WFCState::with_seed(map, 1);
+
+        let mut out = Vec::new();
+        AsciiRenderer::new()
+            .animate(&mut state, &mut out, Duration::ZERO)
+            .unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let frames: Vec<&str> = out.split("\x1b[H").skip(1).collect();
+
+        assert_eq!(frames[0], "    \n    \n    \n");
+        assert_eq!(
+            frames[1]
+                .chars()
+                .filter(|c| *c != ' ' && *c != '\n')
+                .count(),
+            1
+        );
+        assert_eq!(
+            *frames.last().unwrap(),
+            AsciiRenderer::new().render(state.get_map())
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use crate::grid::{Map, TileData, TileType};
+use anyhow::{Context, Result};
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone)]
+pub struct PngOptions {
+    /// Side length of one cell in pixels; sprites are scaled to fit.
+    pub tile_size: u32,
+    /// Directory holding `tile_<name>.png` for every tile type.
+    pub tiles_dir: PathBuf,
+    /// Colour of the one pixel lines drawn between cells, if any.
+    pub grid_color: Option<Rgba<u8>>,
+    /// Fill for cells that have not collapsed; `None` leaves them transparent.
+    pub uncollapsed_color: Option<Rgba<u8>>,
+    /// Seed for picking sprite variants; see `TileData::sprite`.
+    pub seed: u64,
+}
+
+impl Default for PngOptions {
+    fn default() -> Self {
+        Self {
+            tile_size: 32,
+            tiles_dir: PathBuf::from("assets/tiles"),
+            grid_color: None,
+            // Same colour the live view uses for empty cells
+            uncollapsed_color: Some(Rgba([26, 26, 26, 255])),
+            seed: 0,
+        }
+    }
+}
+
+/// Composites tile sprites into a single image of a map. Needs no window or
+/// GPU; sprites are decoded and scaled once when the exporter is created.
+pub struct PngExporter {
+    options: PngOptions,
+    /// Keyed by sprite name, as in `tile_<name>.png`.
+    sprites: HashMap<String, RgbaImage>,
+}
+
+impl PngExporter {
+    /// Loads the sprite of every tile type, and every variant sprite of
+    /// `tile_data`.
+    pub fn new(options: PngOptions, tile_data: &TileData) -> Result<Self> {
+        let variants = tile_data.variants.values().flatten();
+        let names = TileType::ALL
+            .into_iter()
+            .map(|tile_type| tile_type.name())
+            .chain(variants.map(|variant| variant.sprite.as_str()));
+
+        let mut sprites = HashMap::new();
+        for name in names {
+            if !sprites.contains_key(name) {
+                sprites.insert(name.to_string(), load_sprite(&options, name)?);
+            }
+        }
+
+        Ok(Self { options, sprites })
+    }
+
+    /// Fails if `map` uses a variant sprite that was not loaded, i.e. its
+    /// tile data differs from the one the exporter was created with. The
+    /// layers beneath a layered map are drawn first, under its sprites.
+    pub fn render(&self, map: &Map) -> Result<RgbaImage> {
+        let size = self.options.tile_size;
+        let mut image = match map.beneath() {
+            Some(beneath) => self.render(beneath)?,
+            None => RgbaImage::new(map.width as u32 * size, map.height as u32 * size),
+        };
+
+        for (coord, tile) in map.iter() {
+            let x = coord.col as u32 * size;
+            let y = coord.row as u32 * size;
+
+            if let Some(tile_type) = tile.tile_type() {
+                let name = map.tile_data.sprite(tile_type, coord, self.options.seed);
+                let sprite = self
+                    .sprites
+                    .get(name)
+                    .with_context(|| format!("Sprite {} was not loaded", name))?;
+                if map.beneath().is_some() {
+                    imageops::overlay(&mut image, sprite, x as i64, y as i64);
+                } else {
+                    imageops::replace(&mut image, sprite, x as i64, y as i64);
+                }
+            } else if let Some(color) = self.options.uncollapsed_color {
+                for py in y..y + size {
+                    for px in x..x + size {
+                        image.put_pixel(px, py, color);
+                    }
+                }
+            }
+        }
+
+        if let Some(color) = self.options.grid_color {
+            for col in 1..map.width as u32 {
+                for py in 0..image.height() {
+                    image.put_pixel(col * size, py, color);
+                }
+            }
+            for row in 1..map.height as u32 {
+                for px in 0..image.width() {
+                    image.put_pixel(px, row * size, color);
+                }
+            }
+        }
+
+        Ok(image)
+    }
+
+    pub fn export(&self, map: &Map, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        self.render(map)?
+            .save(path)
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+/// Loads `tile_<name>.png` scaled to the tile size.
+fn load_sprite(options: &PngOptions, name: &str) -> Result<RgbaImage> {
+    let size = options.tile_size;
+    let path = options.tiles_dir.join(format!("tile_{}.png", name));
+    let sprite = image::open(&path)
+        .with_context(|| format!("Failed to load sprite {}", path.display()))?
+        .into_rgba8();
+
+    Ok(if sprite.dimensions() == (size, size) {
+        sprite
+    } else {
+        imageops::resize(&sprite, size, size, FilterType::Nearest)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::{Coord, Variant, VerticalRules};
+
+    #[test]
+    fn test_render_composites_sprites_and_marks_uncollapsed() {
+        let mut map = Map::new(3, 2).expect("tile data should load");
+        map.get_tile_mut(Coord::new(1, 2))
+            .reset_domain_to(TileType::Snow.mask());
+
+        let options = PngOptions {
+            tile_size: 8,
+            grid_color: Some(Rgba([255, 0, 0, 255])),
+            ..PngOptions::default()
+        };
+        let uncollapsed = options.uncollapsed_color.unwrap();
+        let exporter = PngExporter::new(options, &map.tile_data).unwrap();
+        let image = exporter.render(&map).unwrap();
+
+        assert_eq!(image.dimensions(), (24, 16));
+        assert_eq!(image.get_pixel(3, 3), &uncollapsed);
+        assert_eq!(image.get_pixel(8, 3), &Rgba([255, 0, 0, 255]));
+        assert_eq!(image.get_pixel(3, 8), &Rgba([255, 0, 0, 255]));
+        assert_eq!(
+            image.get_pixel(20, 12),
+            exporter.sprites["snow"].get_pixel(4, 4)
+        );
+    }
+
+    #[test]
+    fn test_layers_are_drawn_over_the_map_beneath() {
+        let mut terrain = Map::new(1, 1).expect("tile data should load");
+        terrain
+            .get_tile_mut(Coord::new(0, 0))
+            .reset_domain_to(TileType::Grass.mask());
+        let mut tile_data = terrain.tile_data.clone();
+        tile_data.tiles = TileType::Tree.mask();
+        let mut rules = VerticalRules::new();
+        rules.allow(TileType::Grass, TileType::Tree.mask());
+        let layer = terrain.layer_above(tile_data, rules).unwrap();
+
+        let exporter = PngExporter::new(PngOptions::default(), &layer.tile_data).unwrap();
+        let image = exporter.render(&layer).unwrap();
+
+        // The tree's canopy is opaque, the corners around it transparent
+        assert_eq!(
+            image.get_pixel(0, 0),
+            exporter.sprites["grass"].get_pixel(0, 0)
+        );
+        assert_eq!(
+            image.get_pixel(16, 12),
+            exporter.sprites["tree"].get_pixel(16, 12)
+        );
+    }
+
+    #[test]
+    fn test_missing_variant_sprite_is_an_error() {
+        let mut map = Map::new(2, 2).expect("tile data should load");
+        map.tile_data.variants.clear();
+        let options = PngOptions {
+            tile_size: 8,
+            ..PngOptions::default()
+        };
+        let exporter = PngExporter::new(options, &map.tile_data).unwrap();
+
+        let mut varied = Map::new(2, 2).expect("tile data should load");
+        for index in 0..4 {
+            varied
+                .get_tile_mut(Coord::from_index(index, 2))
+                .reset_domain_to(TileType::Mountain.mask());
+        }
+        varied.tile_data.variants.insert(
+            TileType::Mountain,
+            vec![Variant {
+                sprite: String::from("stone"),
+                weight: 1.0,
+            }],
+        );
+        assert!(exporter.render(&varied).is_err());
+    }
+}
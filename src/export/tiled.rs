@@ -0,0 +1,659 @@
+use crate::grid::{Coord, Direction, Domain, Map, TileConstraints, TileData, TileType};
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Tiled marks flipped and rotated tiles in the top bits of a gid.
+const GID_FLAG_MASK: u32 = 0xf000_0000;
+
+#[derive(Debug, Clone)]
+pub struct TiledOptions {
+    pub tile_size: u32,
+    /// Written next to the map as `<tileset_name>.tsx`.
+    pub tileset_name: String,
+    /// Directory holding `tile_<name>.png` for every tile type.
+    pub tiles_dir: PathBuf,
+    /// Seed for picking sprite variants; see `TileData::sprite`.
+    pub seed: u64,
+}
+
+impl Default for TiledOptions {
+    fn default() -> Self {
+        Self {
+            tile_size: 32,
+            tileset_name: String::from("wfc_tiles"),
+            tiles_dir: PathBuf::from("assets/tiles"),
+            seed: 0,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TmjMap {
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    version: String,
+    #[serde(default)]
+    orientation: String,
+    #[serde(default)]
+    renderorder: String,
+    width: usize,
+    height: usize,
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    infinite: bool,
+    #[serde(default)]
+    nextlayerid: u32,
+    #[serde(default)]
+    nextobjectid: u32,
+    layers: Vec<TmjLayer>,
+    tilesets: Vec<TmjTilesetRef>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TmjLayer {
+    #[serde(default)]
+    id: u32,
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    width: usize,
+    #[serde(default)]
+    height: usize,
+    #[serde(default)]
+    x: i32,
+    #[serde(default)]
+    y: i32,
+    #[serde(default = "default_opacity")]
+    opacity: f32,
+    #[serde(default = "default_visible")]
+    visible: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encoding: Option<String>,
+    #[serde(default)]
+    data: Vec<u32>,
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+fn default_visible() -> bool {
+    true
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TmjTilesetRef {
+    firstgid: u32,
+    #[serde(default)]
+    source: String,
+    /// Tiles of an embedded tileset; empty when it lives in `source`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tiles: Vec<TmjTile>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TmjTile {
+    id: u32,
+    #[serde(default)]
+    properties: Vec<TmjProperty>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct TmjProperty {
+    name: String,
+    #[serde(default)]
+    value: serde_json::Value,
+}
+
+/// Writes `map` as a Tiled JSON map (.tmj) at `path`, together with a
+/// `.tsx` image-collection tileset next to it.
+///
+/// The tile id of each tile type is its `repr`, and every tile carries its
+/// tiledata name as the `name` property, so ids never shift when
+/// tiledata.json is reordered. Uncollapsed cells are left empty. Each
+/// sprite variant gets a tile of its own after the base tiles, named after
+/// the tile type it draws, so the map imports back unchanged.
+pub fn export_tiled(map: &Map, path: impl AsRef<Path>, options: &TiledOptions) -> Result<()> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let tileset_file = format!("{}.tsx", options.tileset_name);
+
+    let variants = variant_tiles(&map.tile_data);
+    write_tileset(&dir.join(&tileset_file), options, &variants)?;
+
+    let data = map
+        .iter()
+        .map(|(coord, tile)| {
+            tile.tile_type().map_or(0, |tile_type| {
+                let sprite = map.tile_data.sprite(tile_type, coord, options.seed);
+                let id = variants
+                    .iter()
+                    .position(|&variant| variant == (tile_type, sprite))
+                    .map_or(tile_type as usize, |index| TileType::ALL.len() + index);
+                id as u32 + 1
+            })
+        })
+        .collect();
+
+    let tmj = TmjMap {
+        kind: String::from("map"),
+        version: String::from("1.10"),
+        orientation: String::from("orthogonal"),
+        renderorder: String::from("right-down"),
+        width: map.width,
+        height: map.height,
+        tilewidth: options.tile_size,
+        tileheight: options.tile_size,
+        infinite: false,
+        nextlayerid: 2,
+        nextobjectid: 1,
+        layers: vec![TmjLayer {
+            id: 1,
+            name: String::from("terrain"),
+            kind: String::from("tilelayer"),
+            width: map.width,
+            height: map.height,
+            x: 0,
+            y: 0,
+            opacity: 1.0,
+            visible: true,
+            encoding: None,
+            data,
+        }],
+        tilesets: vec![TmjTilesetRef {
+            firstgid: 1,
+            source: tileset_file,
+            tiles: Vec::new(),
+        }],
+    };
+
+    let mut writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(&mut writer, &tmj)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Every variant sprite a tile type can be drawn with other than its own, in
+/// tile type order. Variant `i` has tile id `TileType::ALL.len() + i`.
+fn variant_tiles(tile_data: &TileData) -> Vec<(TileType, &str)> {
+    let mut tiles = Vec::new();
+    for tile_type in TileType::ALL {
+        for variant in tile_data.variants.get(&tile_type).into_iter().flatten() {
+            let tile = (tile_type, variant.sprite.as_str());
+            if variant.sprite != tile_type.name() && !tiles.contains(&tile) {
+                tiles.push(tile);
+            }
+        }
+    }
+    tiles
+}
+
+fn write_tileset(path: &Path, options: &TiledOptions, variants: &[(TileType, &str)]) -> Result<()> {
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let image_dir = relative_path(dir, &options.tiles_dir)?;
+    let size = options.tile_size;
+
+    let mut xml = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <tileset version=\"1.10\" name=\"{}\" tilewidth=\"{size}\" tileheight=\"{size}\" \
+         tilecount=\"{}\" columns=\"0\">\n \
+         <grid orientation=\"orthogonal\" width=\"1\" height=\"1\"/>\n",
+        escape_xml(&options.tileset_name),
+        TileType::ALL.len() + variants.len(),
+    );
+    let base = TileType::ALL.map(|tile_type| (tile_type, tile_type.name()));
+    for (id, (tile_type, sprite)) in base.iter().chain(variants).enumerate() {
+        let name = tile_type.name();
+        let source = image_dir.join(format!("tile_{sprite}.png"));
+        xml.push_str(&format!(
+            " <tile id=\"{id}\">\n  \
+             <properties>\n   <property name=\"name\" value=\"{name}\"/>\n  </properties>\n  \
+             <image source=\"{}\"/>\n \
+             </tile>\n",
+            escape_xml(&source.to_string_lossy().replace('\\', "/")),
+        ));
+    }
+    xml.push_str("</tileset>\n");
+
+    fs::write(path, xml).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+fn unescape_xml(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Value of `attribute` in the start tag `tag`, e.g. `id` in `<tile id="3">`.
+fn xml_attribute(tag: &str, attribute: &str) -> Option<String> {
+    let pattern = format!("{attribute}=\"");
+    let start = tag
+        .match_indices(&pattern)
+        .map(|(index, _)| index)
+        .find(|&index| tag[..index].ends_with(char::is_whitespace) || index == 0)?
+        + pattern.len();
+    let end = start + tag[start..].find('"')?;
+    Some(unescape_xml(&tag[start..end]))
+}
+
+/// Maps the tile ids of a `.tsx` tileset to tile types by the `name`
+/// property that `export_tiled` gives every tile.
+fn read_tileset_names(path: &Path) -> Result<HashMap<u32, TileType>> {
+    let xml = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read tileset {}", path.display()))?;
+
+    let mut names = HashMap::new();
+    for tile in xml.split("<tile ").skip(1) {
+        let tile = tile.split("</tile>").next().unwrap_or(tile);
+        let Some(id) = xml_attribute(tile, "id").and_then(|id| id.parse().ok()) else {
+            bail!("Tile without an id in {}", path.display());
+        };
+        let Some(name) = tile
+            .split("<property ")
+            .skip(1)
+            .find(|property| xml_attribute(property, "name").as_deref() == Some("name"))
+            .and_then(|property| xml_attribute(property, "value"))
+        else {
+            continue;
+        };
+        names.insert(id, named_tile(id, &name, &path.display().to_string())?);
+    }
+
+    Ok(names)
+}
+
+/// Maps the tile ids of a tileset embedded in the map to tile types by
+/// their `name` property, like `read_tileset_names`.
+fn embedded_tileset_names(tiles: &[TmjTile]) -> Result<HashMap<u32, TileType>> {
+    let mut names = HashMap::new();
+    for tile in tiles {
+        let Some(name) = tile
+            .properties
+            .iter()
+            .find(|property| property.name == "name")
+            .and_then(|property| property.value.as_str())
+        else {
+            continue;
+        };
+        names.insert(tile.id, named_tile(tile.id, name, "the embedded tileset")?);
+    }
+    Ok(names)
+}
+
+fn named_tile(id: u32, name: &str, tileset: &str) -> Result<TileType> {
+    match TileType::ALL.into_iter().find(|t| t.name() == name) {
+        Some(tile_type) => Ok(tile_type),
+        None => bail!(
+            "Tile {} in {} is named {:?}, which is not a tile type",
+            id,
+            tileset,
+            name
+        ),
+    }
+}
+
+/// Path of `target` relative to the directory `from`, as Tiled expects
+/// tileset image sources to be.
+fn relative_path(from: &Path, target: &Path) -> Result<PathBuf> {
+    let from = fs::canonicalize(if from.as_os_str().is_empty() {
+        Path::new(".")
+    } else {
+        from
+    })?;
+    let target = fs::canonicalize(target)
+        .with_context(|| format!("Tile directory {} not found", target.display()))?;
+
+    let from: Vec<Component> = from.components().collect();
+    let target: Vec<Component> = target.components().collect();
+    let common = from.iter().zip(&target).take_while(|(a, b)| a == b).count();
+
+    let mut relative: PathBuf = from[common..].iter().map(|_| "..").collect();
+    relative.extend(&target[common..]);
+    Ok(relative)
+}
+
+/// The first tile layer of a Tiled map written by `export_tiled`.
+#[derive(Debug, Clone)]
+pub struct TiledMap {
+    pub width: usize,
+    pub height: usize,
+    /// Row-major, `None` for empty cells.
+    cells: Vec<Option<TileType>>,
+}
+
+impl TiledMap {
+    /// Reads the map at `path` and the `.tsx` tileset it references,
+    /// resolving every tile by its `name` property.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let tmj: TmjMap = serde_json::from_reader(BufReader::new(file))?;
+
+        let Some(tileset) = tmj.tilesets.first() else {
+            bail!("Tiled map has no tileset");
+        };
+        let Some(layer) = tmj.layers.iter().find(|layer| layer.kind == "tilelayer") else {
+            bail!("Tiled map has no tile layer");
+        };
+        if let Some(encoding) = layer.encoding.as_deref().filter(|&e| e != "csv") {
+            bail!(
+                "Unsupported layer encoding {:?}, save the map as CSV",
+                encoding
+            );
+        }
+        let Some(cell_count) = tmj.width.checked_mul(tmj.height) else {
+            bail!(
+                "Tiled map of {}x{} cells is too large",
+                tmj.width,
+                tmj.height
+            );
+        };
+        if layer.data.len() != cell_count {
+            bail!(
+                "Tile layer has {} cells but the map is {}x{}",
+                layer.data.len(),
+                tmj.width,
+                tmj.height
+            );
+        }
+
+        // Tiles are matched by their name property, so a tileset edited or
+        // reordered in Tiled still imports, whether embedded or in a .tsx
+        let names = if tileset.source.is_empty() {
+            embedded_tileset_names(&tileset.tiles)?
+        } else {
+            let dir = path.parent().unwrap_or(Path::new(""));
+            read_tileset_names(&dir.join(&tileset.source))?
+        };
+
+        let cells = layer
+            .data
+            .iter()
+            .map(|&gid| {
+                let gid = gid & !GID_FLAG_MASK;
+                if gid == 0 {
+                    return Ok(None);
+                }
+
+                gid.checked_sub(tileset.firstgid)
+                    .and_then(|id| names.get(&id).copied())
+                    .map(Some)
+                    .ok_or_else(|| anyhow::anyhow!("Tile gid {} is not part of the tileset", gid))
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            width: tmj.width,
+            height: tmj.height,
+            cells,
+        })
+    }
+
+    pub fn get(&self, coord: Coord) -> Option<TileType> {
+        self.cells[coord.to_index(self.width)]
+    }
+
+    /// A map with every painted cell pinned to its tile and the empty cells
+    /// left open, ready to be completed by the solver.
+    pub fn pinned_map(&self, tile_data: TileData) -> Result<Map> {
+        let mut map = Map::with_tile_data(self.width, self.height, tile_data);
+
+        for (index, cell) in self.cells.iter().enumerate() {
+            let Some(tile_type) = cell else {
+                continue;
+            };
+            let coord = Coord::from_index(index, self.width);
+            if map
+                .tile_data
+                .tiles
+                .intersection(tile_type.mask())
+                .is_empty()
+            {
+                bail!(
+                    "Tile {} at ({}, {}) is not in the tileset",
+                    tile_type.name(),
+                    coord.row,
+                    coord.col
+                );
+            }
+            map.get_tile_mut(coord).reset_domain_to(tile_type.mask());
+        }
+
+        Ok(map)
+    }
+
+    /// Treats the painted cells as an example and allows exactly the
+    /// adjacencies that occur in it.
+    pub fn learn_tile_data(&self) -> TileData {
+        let mut tiles = Domain::empty();
+        let mut supports: HashMap<TileType, TileConstraints> = HashMap::new();
+
+        for (index, cell) in self.cells.iter().enumerate() {
+            let Some(tile_type) = *cell else {
+                continue;
+            };
+            tiles.add_tiles(tile_type.mask());
+
+            let constraints = supports.entry(tile_type).or_insert(TileConstraints {
+                top: Domain::empty(),
+                right: Domain::empty(),
+                bottom: Domain::empty(),
+                left: Domain::empty(),
+            });
+
+            let coord = Coord::from_index(index, self.width);
+            for (direction, neighbour) in coord
+                .neighbours(self.height, self.width)
+                .into_iter()
+                .flatten()
+            {
+                let Some(neighbour_type) = self.get(neighbour) else {
+                    continue;
+                };
+                let allowed = match direction {
+                    Direction::Top => &mut constraints.top,
+                    Direction::Bottom => &mut constraints.bottom,
+                    Direction::Left => &mut constraints.left,
+                    Direction::Right => &mut constraints.right,
+                };
+                allowed.add_tiles(neighbour_type.mask());
+            }
+        }
+
+        TileData {
+            tiles,
+            supports,
+            big_tiles: Vec::new(),
+            variants: HashMap::new(),
+            mirrored: HashMap::new(),
+            rotated: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wfc::WFCState;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("wfc-tiled-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_export_then_import_round_trips() {
+        let mut map = Map::new(6, 4).expect("tile data should load");
+        map.get_tile_mut(Coord::new(0, 0))
+            .reset_domain_to(TileType::DeepWater.mask());
+        map.get_tile_mut(Coord::new(3, 5))
+            .reset_domain_to(TileType::MountainSnowW.mask());
+
+        let dir = temp_dir("round-trip");
+        let path = dir.join("map.tmj");
+        export_tiled(&map, &path, &TiledOptions::default()).unwrap();
+
+        let tileset = fs::read_to_string(dir.join("wfc_tiles.tsx")).unwrap();
+        assert!(tileset.contains("<tile id=\"24\">"));
+        assert!(tileset.contains("value=\"mountain_snow_w\""));
+
+        let imported = TiledMap::load(&path).unwrap();
+        assert_eq!((imported.width, imported.height), (6, 4));
+        assert_eq!(imported.get(Coord::new(0, 0)), Some(TileType::DeepWater));
+        assert_eq!(
+            imported.get(Coord::new(3, 5)),
+            Some(TileType::MountainSnowW)
+        );
+        assert_eq!(imported.get(Coord::new(1, 1)), None);
+
+        let pinned = imported.pinned_map(map.tile_data.clone()).unwrap();
+        assert_eq!(pinned.tiles(), map.tiles());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_import_resolves_tiles_by_name() {
+        let mut map = Map::new(3, 1).expect("tile data should load");
+        map.get_tile_mut(Coord::new(0, 1))
+            .reset_domain_to(TileType::Forest.mask());
+
+        let dir = temp_dir("names");
+        let path = dir.join("map.tmj");
+        let options = TiledOptions {
+            tileset_name: String::from("rock & roll"),
+            ..TiledOptions::default()
+        };
+        export_tiled(&map, &path, &options).unwrap();
+
+        let tileset_path = dir.join("rock & roll.tsx");
+        let tileset = fs::read_to_string(&tileset_path).unwrap();
+        assert!(tileset.contains("name=\"rock &amp; roll\""));
+
+        // Swap the names of forest and grass as if edited in Tiled
+        let swapped = tileset
+            .replace("value=\"forest\"", "value=\"placeholder\"")
+            .replace("value=\"grass\"", "value=\"forest\"")
+            .replace("value=\"placeholder\"", "value=\"grass\"");
+        fs::write(&tileset_path, swapped).unwrap();
+
+        let imported = TiledMap::load(&path).unwrap();
+        assert_eq!(imported.get(Coord::new(0, 1)), Some(TileType::Grass));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_variants_get_their_own_tiles() {
+        let mut map = Map::new(8, 8).expect("tile data should load");
+        for index in 0..64 {
+            map.get_tile_mut(Coord::from_index(index, 8))
+                .reset_domain_to(TileType::Mountain.mask());
+        }
+
+        let dir = temp_dir("variants");
+        let path = dir.join("map.tmj");
+        export_tiled(&map, &path, &TiledOptions::default()).unwrap();
+
+        let tileset = fs::read_to_string(dir.join("wfc_tiles.tsx")).unwrap();
+        assert!(tileset.contains("tile_stone.png"));
+        let tmj: TmjMap = serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        let base = TileType::Mountain as u32 + 1;
+        assert!(tmj.layers[0].data.contains(&base));
+        assert!(
+            tmj.layers[0]
+                .data
+                .iter()
+                .any(|&gid| gid as usize > TileType::ALL.len())
+        );
+
+        let imported = TiledMap::load(&path).unwrap();
+        assert!(
+            imported
+                .cells
+                .iter()
+                .all(|&cell| cell == Some(TileType::Mountain))
+        );
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_embedded_tileset_resolves_tiles_by_name() {
+        let dir = temp_dir("embedded");
+        let path = dir.join("map.tmj");
+        let tmj = r#"{
+            "width": 2, "height": 1, "tilewidth": 32, "tileheight": 32,
+            "layers": [{"type": "tilelayer", "data": [3, 4]}],
+            "tilesets": [{
+                "firstgid": 3,
+                "tiles": [
+                    {"id": 0, "properties": [{"name": "name", "type": "string", "value": "snow"}]},
+                    {"id": 1, "properties": [{"name": "name", "type": "string", "value": "beach"}]}
+                ]
+            }]
+        }"#;
+        fs::write(&path, tmj).unwrap();
+
+        let imported = TiledMap::load(&path).unwrap();
+        assert_eq!(imported.get(Coord::new(0, 0)), Some(TileType::Snow));
+        assert_eq!(imported.get(Coord::new(0, 1)), Some(TileType::Beach));
+
+        // 2^63 + 1 by 2 cells wraps around to the 2 cells of the layer
+        let oversized = tmj
+            .replace("\"width\": 2", "\"width\": 9223372036854775809")
+            .replace("\"height\": 1", "\"height\": 2");
+        fs::write(&path, oversized).unwrap();
+        let error = TiledMap::load(&path).unwrap_err();
+        assert!(error.to_string().contains("too large"), "{}", error);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_learned_rules_allow_the_example() {
+        let map = Map::new(10, 10).expect("tile data should load");
+        let mut state = WFCState::with_seed(map, 2);
+        state.solve().unwrap();
+
+        let dir = temp_dir("learn");
+        let path = dir.join("example.tmj");
+        export_tiled(state.get_map(), &path, &TiledOptions::default()).unwrap();
+        let example = TiledMap::load(&path).unwrap();
+        fs::remove_dir_all(dir).unwrap();
+
+        let learned = example.learn_tile_data();
+        for (coord, tile) in state.get_map().iter() {
+            let tile_type = tile.tile_type().unwrap();
+            assert!(!learned.tiles.intersection(tile_type.mask()).is_empty());
+
+            for (direction, neighbour) in coord.neighbours(10, 10).into_iter().flatten() {
+                let allowed = learned
+                    .supported_neighbours(tile_type.mask(), direction)
+                    .unwrap();
+                let neighbour_domain = state.get_map().get_tile(neighbour).current_domain;
+                assert!(!allowed.intersection(neighbour_domain).is_empty());
+            }
+        }
+    }
+}
@@ -1,9 +1,30 @@
+mod big_tile;
+mod biome;
 mod coord;
+mod counterparts;
 mod map;
+mod map_file;
+mod prior;
+mod region;
+mod scalar_field;
 mod tile;
 mod tile_data;
+mod tile_weights;
+mod variant;
+mod vertical_rules;
 
+pub use big_tile::BigTile;
+pub use biome::{Biome, BiomeMask};
 pub use coord::{Coord, Direction};
 pub use map::Map;
+pub use map_file::{FORMAT_VERSION, MapFileError, MapFormat};
+pub use prior::FieldPrior;
+pub use region::Region;
+pub use scalar_field::ScalarField;
 pub use tile::Tile;
 pub use tile_data::{Domain, TileConstraints, TileData, TileType};
+pub use tile_weights::TileWeights;
+pub(crate) use tile_weights::pick_weighted;
+pub use variant::Variant;
+pub(crate) use variant::{default_weight, splitmix64};
+pub use vertical_rules::VerticalRules;
@@ -0,0 +1,112 @@
+use super::coord::{Coord, Direction};
+use super::map::Map;
+use super::tile_data::{TileData, TileType};
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+
+fn default_chance() -> f32 {
+    0.1
+}
+
+/// A fixed pattern of ordinary tile types the solver can stamp over several
+/// cells at once, such as a ring of shallow water around deep water. It has
+/// no sprite of its own: exporters draw and save the covered cells like any
+/// others, and which pattern was placed where is only kept while solving.
+/// Because every covered cell takes an ordinary tile type, the adjacency
+/// rules of those tiles hold along the footprint's outer edges.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BigTile {
+    pub name: String,
+    /// Tile type of every covered cell, row by row, starting with the anchor
+    /// at the top left.
+    pub cells: Vec<Vec<TileType>>,
+    /// Chance of placing the tile when the solver collapses a cell it could
+    /// be anchored at.
+    #[serde(default = "default_chance")]
+    pub chance: f32,
+}
+
+impl BigTile {
+    pub fn width(&self) -> usize {
+        self.cells.first().map_or(0, Vec::len)
+    }
+
+    pub fn height(&self) -> usize {
+        self.cells.len()
+    }
+
+    /// Covered cells and their tile types when anchored at `anchor`, anchor
+    /// first.
+    pub fn footprint(&self, anchor: Coord) -> impl Iterator<Item = (Coord, TileType)> + '_ {
+        self.cells.iter().enumerate().flat_map(move |(row, tiles)| {
+            tiles.iter().enumerate().map(move |(col, &tile_type)| {
+                (Coord::new(anchor.row + row, anchor.col + col), tile_type)
+            })
+        })
+    }
+
+    /// Whether the tile can be anchored at `anchor`: the footprint lies
+    /// inside the map and every covered cell is open and still allows its
+    /// tile type.
+    pub fn fits(&self, map: &Map, anchor: Coord) -> bool {
+        if anchor.row + self.height() > map.height || anchor.col + self.width() > map.width {
+            return false;
+        }
+
+        self.footprint(anchor).all(|(coord, tile_type)| {
+            let tile = map.get_tile(coord);
+            !tile.is_collapsed()
+                && !tile
+                    .current_domain
+                    .intersection(tile_type.mask())
+                    .is_empty()
+        })
+    }
+
+    /// Checks that the footprint is a non-empty rectangle of tiles from the
+    /// tileset whose inner edges follow the adjacency rules.
+    pub(super) fn validate(&self, tile_data: &TileData) -> Result<()> {
+        let width = self.width();
+        if width == 0 || self.cells.iter().any(|row| row.len() != width) {
+            bail!("Big tile {} must be a non-empty rectangle", self.name);
+        }
+        if !(0.0..=1.0).contains(&self.chance) {
+            bail!(
+                "Big tile {} has chance {} outside [0, 1]",
+                self.name,
+                self.chance
+            );
+        }
+
+        let origin = Coord::new(0, 0);
+        for (coord, tile_type) in self.footprint(origin) {
+            if tile_data.tiles.intersection(tile_type.mask()).is_empty() {
+                bail!(
+                    "Big tile {} uses {}, which is not in the tileset",
+                    self.name,
+                    tile_type.name()
+                );
+            }
+
+            for (direction, neighbour) in coord
+                .neighbours(self.height(), width)
+                .into_iter()
+                .flatten()
+                .filter(|(direction, _)| matches!(direction, Direction::Right | Direction::Bottom))
+            {
+                let neighbour_type = self.cells[neighbour.row][neighbour.col];
+                let allowed = tile_data.supported_neighbours(tile_type.mask(), direction)?;
+                if allowed.intersection(neighbour_type.mask()).is_empty() {
+                    bail!(
+                        "Big tile {} puts {} next to {}, which the adjacency rules forbid",
+                        self.name,
+                        tile_type.name(),
+                        neighbour_type.name()
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
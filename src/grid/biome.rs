@@ -0,0 +1,206 @@
+use super::tile_data::{Domain, TileData, TileType};
+use super::{Coord, Map, TileWeights};
+use anyhow::{Context, Result, bail};
+use std::path::Path;
+
+/// A designer-painted area type that limits and biases the tiles of the
+/// cells it covers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Biome {
+    pub name: String,
+    /// Tiles a cell of this biome may become.
+    pub allowed: Domain,
+    /// Multipliers for the weights of individual tiles.
+    pub weights: Vec<(TileType, f32)>,
+}
+
+impl Biome {
+    pub fn new(name: impl Into<String>, allowed: Domain) -> Self {
+        Self {
+            name: name.into(),
+            allowed,
+            weights: Vec::new(),
+        }
+    }
+
+    pub fn with_weight(mut self, tile_type: TileType, factor: f32) -> Self {
+        self.weights.push((tile_type, factor));
+        self
+    }
+}
+
+/// Assigns every cell of a map one of `biomes`, row-major like `Map`.
+#[derive(Debug, Clone)]
+pub struct BiomeMask {
+    pub width: usize,
+    pub height: usize,
+    pub biomes: Vec<Biome>,
+    labels: Vec<usize>,
+}
+
+impl BiomeMask {
+    /// `labels` holds the index into `biomes` of each cell.
+    pub fn from_labels(
+        width: usize,
+        height: usize,
+        biomes: Vec<Biome>,
+        labels: Vec<usize>,
+    ) -> Result<Self> {
+        if labels.len() != width * height {
+            bail!(
+                "Biome mask has {} labels for a {}x{} map",
+                labels.len(),
+                width,
+                height
+            );
+        }
+        if let Some(label) = labels.iter().find(|&&label| label >= biomes.len()) {
+            bail!("Biome label {} but only {} biomes", label, biomes.len());
+        }
+
+        Ok(Self {
+            width,
+            height,
+            biomes,
+            labels,
+        })
+    }
+
+    /// Reads the labels from a painted image, scaled to `width` x `height`
+    /// cells by nearest-neighbour sampling. `palette` gives the RGB colour
+    /// that stands for each biome.
+    pub fn from_image(
+        path: impl AsRef<Path>,
+        width: usize,
+        height: usize,
+        palette: Vec<([u8; 3], Biome)>,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let image = image::open(path)
+            .with_context(|| format!("Failed to load biome mask {}", path.display()))?
+            .into_rgb8();
+
+        let labels = (0..width * height)
+            .map(|index| {
+                let coord = Coord::from_index(index, width);
+                let x = (coord.col * image.width() as usize / width) as u32;
+                let y = (coord.row * image.height() as usize / height) as u32;
+                let color = image.get_pixel(x, y).0;
+
+                palette
+                    .iter()
+                    .position(|(biome_color, _)| *biome_color == color)
+                    .ok_or_else(|| {
+                        anyhow::anyhow!("Colour {:?} at pixel ({}, {}) is not a biome", color, x, y)
+                    })
+            })
+            .collect::<Result<_>>()?;
+
+        let biomes = palette.into_iter().map(|(_, biome)| biome).collect();
+        Self::from_labels(width, height, biomes, labels)
+    }
+
+    pub fn biome_at(&self, coord: Coord) -> &Biome {
+        &self.biomes[self.labels[coord.to_index(self.width)]]
+    }
+}
+
+impl Map {
+    /// A fresh map whose initial domains are limited, and whose weights are
+    /// scaled, by the biome of each cell.
+    pub fn with_biomes(tile_data: TileData, mask: &BiomeMask) -> Result<Self> {
+        let mut map = Self::with_tile_data(mask.width, mask.height, tile_data);
+        let reweighted = mask.biomes.iter().any(|biome| !biome.weights.is_empty());
+
+        // One weight table per biome, shared by all of its cells
+        let tables: Vec<usize> = mask
+            .biomes
+            .iter()
+            .map(|biome| {
+                let mut weights = TileWeights::uniform();
+                for &(tile_type, factor) in &biome.weights {
+                    weights.scale(tile_type, factor);
+                }
+                map.add_weight_table(weights)
+            })
+            .collect();
+
+        for index in 0..mask.width * mask.height {
+            let coord = map.coord(index);
+            let label = mask.labels[index];
+            let biome = &mask.biomes[label];
+
+            let domain = map.tile_data.tiles.intersection(biome.allowed);
+            if domain.is_empty() {
+                bail!("Biome {} allows no tile of the tileset", biome.name);
+            }
+            map.get_tile_mut(coord).reset_domain_to(domain);
+
+            if reweighted {
+                map.set_weight_table(coord, tables[label])?;
+            }
+        }
+
+        Ok(map)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgb, RgbImage};
+
+    fn mountains() -> Biome {
+        Biome::new(
+            "mountains",
+            TileType::Mountain.mask() | TileType::Snow.mask() | TileType::MountainSnowN.mask(),
+        )
+        .with_weight(TileType::Snow, 0.0)
+    }
+
+    fn lowlands() -> Biome {
+        Biome::new(
+            "lowlands",
+            !(TileType::Mountain.mask() | TileType::Snow.mask()),
+        )
+    }
+
+    #[test]
+    fn test_biomes_restrict_and_reweight_initial_domains() {
+        let tile_data = TileData::load("assets/tiledata.json").unwrap();
+        let mask =
+            BiomeMask::from_labels(2, 2, vec![mountains(), lowlands()], vec![0, 0, 1, 1]).unwrap();
+        let map = Map::with_biomes(tile_data.clone(), &mask).unwrap();
+
+        let top = map.get_tile(Coord::new(0, 1)).current_domain;
+        assert_eq!(top, mountains().allowed);
+        assert_eq!(
+            map.weights(Coord::new(0, 1)).unwrap().get(TileType::Snow),
+            0.0
+        );
+
+        let bottom = map.get_tile(Coord::new(1, 0)).current_domain;
+        assert_eq!(bottom, tile_data.tiles.intersection(lowlands().allowed));
+        assert_eq!(
+            map.weights(Coord::new(1, 0)).unwrap().get(TileType::Snow),
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_mask_from_image_scales_to_the_map() {
+        let mut image = RgbImage::from_pixel(4, 4, Rgb([0, 128, 0]));
+        for x in 0..4 {
+            image.put_pixel(x, 0, Rgb([255, 255, 255]));
+        }
+        let path = std::env::temp_dir().join(format!("wfc-biomes-{}.png", std::process::id()));
+        image.save(&path).unwrap();
+
+        let palette = vec![([255, 255, 255], mountains()), ([0, 128, 0], lowlands())];
+        let mask = BiomeMask::from_image(&path, 8, 8, palette).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(mask.biome_at(Coord::new(1, 5)).name, "mountains");
+        assert_eq!(mask.biome_at(Coord::new(2, 5)).name, "lowlands");
+    }
+}
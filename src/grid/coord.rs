@@ -1,6 +1,9 @@
+#[cfg(feature = "bevy")]
 use bevy::ecs::component::Component;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Component)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[cfg_attr(feature = "bevy", derive(Component))]
 pub struct Coord {
     pub row: usize,
     pub col: usize,
@@ -14,11 +17,38 @@ pub enum Direction {
     Right,
 }
 
+impl Direction {
+    pub const ALL: [Direction; 4] = [
+        Direction::Top,
+        Direction::Bottom,
+        Direction::Left,
+        Direction::Right,
+    ];
+
+    pub fn opposite(self) -> Self {
+        match self {
+            Direction::Top => Direction::Bottom,
+            Direction::Bottom => Direction::Top,
+            Direction::Left => Direction::Right,
+            Direction::Right => Direction::Left,
+        }
+    }
+}
+
 impl Coord {
     pub fn new(row: usize, col: usize) -> Self {
         Self { row, col }
     }
 
+    /// Row-major index of this coord in a grid `width` cells wide.
+    pub fn to_index(self, width: usize) -> usize {
+        self.row * width + self.col
+    }
+
+    pub fn from_index(index: usize, width: usize) -> Self {
+        Self::new(index / width, index % width)
+    }
+
     pub fn neighbours(&self, max_row: usize, max_col: usize) -> [Option<(Direction, Coord)>; 4] {
         [
             if self.row > 0 {
@@ -44,3 +74,35 @@ impl Coord {
         ]
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_index_round_trip() {
+        let width = 7;
+        for index in 0..width * 5 {
+            let coord = Coord::from_index(index, width);
+            assert!(coord.col < width);
+            assert_eq!(coord.to_index(width), index);
+        }
+        assert_eq!(Coord::new(2, 3).to_index(width), 17);
+    }
+
+    #[test]
+    fn test_neighbours_respect_non_square_bounds() {
+        let neighbours: Vec<_> = Coord::new(0, 4)
+            .neighbours(2, 5)
+            .into_iter()
+            .flatten()
+            .collect();
+        assert_eq!(
+            neighbours,
+            vec![
+                (Direction::Left, Coord::new(0, 3)),
+                (Direction::Bottom, Coord::new(1, 4)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use super::tile_data::{TileData, TileType};
+use anyhow::{Result, bail};
+
+impl TileData {
+    /// The tile as it appears in a map mirrored left to right, e.g. a
+    /// transition with its east and west sides swapped. Tiles without a
+    /// declared counterpart are unchanged.
+    pub fn mirrored(&self, tile_type: TileType) -> TileType {
+        self.mirrored.get(&tile_type).copied().unwrap_or(tile_type)
+    }
+
+    /// The tile as it appears in a map turned a quarter turn clockwise, e.g.
+    /// a transition with its side moved from north to east. Tiles without a
+    /// declared counterpart are unchanged.
+    pub fn rotated(&self, tile_type: TileType) -> TileType {
+        self.rotated.get(&tile_type).copied().unwrap_or(tile_type)
+    }
+
+    /// Checks that mirroring twice and rotating four times give back every
+    /// tile, so symmetric maps can be built from the counterparts.
+    pub(super) fn validate_counterparts(&self) -> Result<()> {
+        for tile_type in TileType::ALL {
+            let mirrored = self.mirrored(tile_type);
+            if self.mirrored(mirrored) != tile_type {
+                bail!(
+                    "{} mirrors to {}, which does not mirror back",
+                    tile_type.name(),
+                    mirrored.name()
+                );
+            }
+
+            let turned = (0..4).fold(tile_type, |turned, _| self.rotated(turned));
+            if turned != tile_type {
+                bail!(
+                    "{} turns into {} after four quarter turns",
+                    tile_type.name(),
+                    turned.name()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counterparts_come_from_the_tileset() {
+        let mut tile_data = TileData::load("assets/tiledata.json").unwrap();
+        assert_eq!(
+            tile_data.mirrored(TileType::BeachWaterNe),
+            TileType::BeachWaterNw
+        );
+        assert_eq!(
+            tile_data.rotated(TileType::MountainSnowW),
+            TileType::MountainSnowN
+        );
+        assert_eq!(tile_data.rotated(TileType::Grass), TileType::Grass);
+
+        tile_data
+            .mirrored
+            .insert(TileType::GrassForestE, TileType::GrassForestN);
+        assert!(tile_data.validate_counterparts().is_err());
+    }
+}
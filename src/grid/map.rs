@@ -1,32 +1,391 @@
-use super::tile_data::TileData;
-use super::{Coord, Tile};
-use anyhow::Result;
+use super::tile_data::{Domain, TileData, TileType};
+use super::{BigTile, Coord, Region, Tile, TileWeights, VerticalRules};
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Map {
     pub width: usize,
     pub height: usize,
     pub tile_data: TileData,
-    pub tiles: Vec<Vec<Tile>>,
+    /// Row-major cell storage, addressed via `Coord::to_index`.
+    tiles: Vec<Tile>,
+    /// Weight tables shared between cells, e.g. one per biome.
+    #[serde(default)]
+    weight_tables: Vec<TileWeights>,
+    /// Index into `weight_tables` of each cell, laid out like `tiles`. Empty
+    /// until a cell is given weights, meaning every choice is uniform.
+    #[serde(default)]
+    weight_ids: Vec<u32>,
+    /// Anchors of the big tiles placed so far, with their index into
+    /// `tile_data.big_tiles`.
+    #[serde(default)]
+    big_tiles: Vec<(Coord, usize)>,
+    /// The solved layer this map sits on, if it was made by `layer_above`.
+    #[serde(default)]
+    beneath: Option<Box<Beneath>>,
+}
+
+/// A solved layer under a map, with the rules for stacking tiles on it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Beneath {
+    map: Map,
+    rules: VerticalRules,
 }
 
 impl Map {
     pub fn new(width: usize, height: usize) -> Result<Self> {
         let tile_data = TileData::load("assets/tiledata.json")?;
+        Ok(Self::with_tile_data(width, height, tile_data))
+    }
+
+    pub fn with_tile_data(width: usize, height: usize, tile_data: TileData) -> Self {
         let domain = tile_data.tiles;
-        let tiles = (0..height)
-            .map(|_| (0..width).map(|_| Tile::new(None, domain)).collect())
-            .collect();
+        let tiles = vec![Tile::new(domain); width * height];
 
-        Ok(Self {
+        Self {
             width,
             height,
             tile_data,
             tiles,
-        })
+            weight_tables: Vec::new(),
+            weight_ids: Vec::new(),
+            big_tiles: Vec::new(),
+            beneath: None,
+        }
+    }
+
+    /// Builds a map from row-major cells, e.g. when loading a saved map.
+    pub(super) fn from_tiles(
+        width: usize,
+        height: usize,
+        tile_data: TileData,
+        tiles: Vec<Tile>,
+    ) -> Self {
+        debug_assert_eq!(tiles.len(), width * height);
+        Self {
+            width,
+            height,
+            tile_data,
+            tiles,
+            weight_tables: Vec::new(),
+            weight_ids: Vec::new(),
+            big_tiles: Vec::new(),
+            beneath: None,
+        }
+    }
+
+    /// Starts a layer on top of this solved map, e.g. decorations over
+    /// terrain. Each cell of the layer allows the tiles of `tile_data` that
+    /// `rules` allow on the tile beneath it; the layer is then solved like
+    /// any other map.
+    pub fn layer_above(self, tile_data: TileData, rules: VerticalRules) -> Result<Map> {
+        let mut layer = Map::with_tile_data(self.width, self.height, tile_data);
+        layer.beneath = Some(Box::new(Beneath { map: self, rules }));
+
+        for index in 0..layer.tiles.len() {
+            let domain = layer.initial_domain(layer.coord(index))?;
+            layer.tiles[index].reset_domain_to(domain);
+        }
+        Ok(layer)
+    }
+
+    /// The layer this map sits on, if it was made by `layer_above`.
+    pub fn beneath(&self) -> Option<&Map> {
+        self.beneath.as_ref().map(|beneath| &beneath.map)
+    }
+
+    /// Tiles the cell at `coord` starts out with: the whole tileset, or on
+    /// top of another layer the tiles allowed on the tile beneath.
+    fn initial_domain(&self, coord: Coord) -> Result<Domain> {
+        let tiles = self.tile_data.tiles;
+        let Some(beneath) = &self.beneath else {
+            return Ok(tiles);
+        };
+
+        let Some(below) = beneath.map.get_tile(coord).tile_type() else {
+            bail!(
+                "The layer beneath is not solved at ({}, {})",
+                coord.row,
+                coord.col
+            );
+        };
+        let domain = tiles.intersection(beneath.rules.allowed_on(below));
+        if domain.is_empty() {
+            bail!(
+                "No tile may sit on {} at ({}, {})",
+                below.name(),
+                coord.row,
+                coord.col
+            );
+        }
+        Ok(domain)
+    }
+
+    pub fn contains(&self, coord: Coord) -> bool {
+        coord.row < self.height && coord.col < self.width
+    }
+
+    /// Index of `coord` in the flat tile buffer. Panics if `coord` is
+    /// outside the map rather than aliasing a cell of another row.
+    pub fn index(&self, coord: Coord) -> usize {
+        assert!(
+            self.contains(coord),
+            "({}, {}) is outside the {}x{} map",
+            coord.row,
+            coord.col,
+            self.width,
+            self.height
+        );
+        coord.to_index(self.width)
+    }
+
+    pub fn coord(&self, index: usize) -> Coord {
+        Coord::from_index(index, self.width)
+    }
+
+    pub fn get_tile(&self, coord: Coord) -> &Tile {
+        &self.tiles[self.index(coord)]
     }
 
     pub fn get_tile_mut(&mut self, coord: Coord) -> &mut Tile {
-        &mut self.tiles[coord.row][coord.col]
+        let index = self.index(coord);
+        &mut self.tiles[index]
+    }
+
+    /// Weights used when collapsing the cell at `coord`, or `None` if no
+    /// cell has weights and choices are uniform.
+    pub fn weights(&self, coord: Coord) -> Option<&TileWeights> {
+        let id = self.weight_table(coord)?;
+        Some(&self.weight_tables[id])
+    }
+
+    /// Id of the weight table the cell at `coord` uses, if any.
+    pub fn weight_table(&self, coord: Coord) -> Option<usize> {
+        self.weight_ids
+            .get(self.index(coord))
+            .map(|&id| id as usize)
+    }
+
+    /// Adds a weight table that cells can share with `set_weight_table`
+    /// and returns its id. Adding an identical table again returns the id
+    /// of the existing one.
+    pub fn add_weight_table(&mut self, weights: TileWeights) -> usize {
+        if let Some(id) = self
+            .weight_tables
+            .iter()
+            .position(|table| *table == weights)
+        {
+            return id;
+        }
+        self.weight_tables.push(weights);
+        self.weight_tables.len() - 1
+    }
+
+    /// Makes the cell at `coord` use the weight table `id`, switching the
+    /// map over to weighted choices (uniform everywhere else) on first use.
+    pub fn set_weight_table(&mut self, coord: Coord, id: usize) -> Result<()> {
+        if id >= self.weight_tables.len() {
+            bail!(
+                "No weight table {}, the map has {}",
+                id,
+                self.weight_tables.len()
+            );
+        }
+        let index = self.index(coord);
+        if self.weight_ids.is_empty() {
+            let uniform = self.add_weight_table(TileWeights::uniform()) as u32;
+            self.weight_ids = vec![uniform; self.tiles.len()];
+        }
+        self.weight_ids[index] = id as u32;
+        Ok(())
+    }
+
+    /// Collapses the cell at `coord` using its weights, if any.
+    pub fn collapse_cell(
+        &mut self,
+        coord: Coord,
+        rng: &mut impl rand::Rng,
+    ) -> Result<(TileType, Domain)> {
+        let index = self.index(coord);
+        let weights = self
+            .weight_ids
+            .get(index)
+            .map(|&id| &self.weight_tables[id as usize]);
+        self.tiles[index].collapse_self(rng, weights)
+    }
+
+    pub fn tiles(&self) -> &[Tile] {
+        &self.tiles
+    }
+
+    /// Resets every cell in `region` to the full domain of the tileset, or
+    /// what the layer beneath allows, leaving the rest of the map untouched.
+    pub fn reset_region(&mut self, region: &Region) -> Result<()> {
+        let cells = region.cells(self)?;
+        for &coord in &cells {
+            let domain = self.initial_domain(coord)?;
+            self.get_tile_mut(coord).reset_domain_to(domain);
+        }
+
+        // A big tile cut by the region no longer covers its footprint
+        let placed = std::mem::take(&mut self.big_tiles);
+        self.big_tiles = placed
+            .into_iter()
+            .filter(|&(anchor, index)| {
+                self.tile_data.big_tiles[index]
+                    .footprint(anchor)
+                    .all(|(coord, _)| !cells.contains(&coord))
+            })
+            .collect();
+        Ok(())
+    }
+
+    /// Big tiles placed on the map, with their anchor cells.
+    pub fn big_tiles(&self) -> impl Iterator<Item = (Coord, &BigTile)> {
+        self.big_tiles
+            .iter()
+            .map(|&(anchor, index)| (anchor, &self.tile_data.big_tiles[index]))
+    }
+
+    /// Records big tile `index` as anchored at `anchor`. The covered cells
+    /// are collapsed by the solver, not here.
+    pub(crate) fn place_big_tile(&mut self, anchor: Coord, index: usize) {
+        self.big_tiles.push((anchor, index));
+    }
+
+    pub(crate) fn remove_big_tile(&mut self, anchor: Coord) {
+        self.big_tiles.retain(|&(placed, _)| placed != anchor);
+    }
+
+    /// Checks that the cells, weight tables and big tiles of a deserialized
+    /// map agree with its size and tileset.
+    pub(crate) fn validate(&self) -> Result<()> {
+        if self.width.checked_mul(self.height) != Some(self.tiles.len()) {
+            bail!(
+                "Map has {} cells but is {}x{}",
+                self.tiles.len(),
+                self.width,
+                self.height
+            );
+        }
+
+        if !self.weight_ids.is_empty() && self.weight_ids.len() != self.tiles.len() {
+            bail!(
+                "Map has {} weight table ids for {} cells",
+                self.weight_ids.len(),
+                self.tiles.len()
+            );
+        }
+        if let Some(&id) = self
+            .weight_ids
+            .iter()
+            .find(|&&id| id as usize >= self.weight_tables.len())
+        {
+            bail!(
+                "Map refers to weight table {} but has {}",
+                id,
+                self.weight_tables.len()
+            );
+        }
+
+        let big_tiles = self.tile_data.big_tiles.len();
+        for &(anchor, index) in &self.big_tiles {
+            if !self.contains(anchor) {
+                bail!(
+                    "Big tile anchored at ({}, {}) is outside the {}x{} map",
+                    anchor.row,
+                    anchor.col,
+                    self.width,
+                    self.height
+                );
+            }
+            if index >= big_tiles {
+                bail!(
+                    "Map places big tile {} but the tileset has {}",
+                    index,
+                    big_tiles
+                );
+            }
+        }
+
+        if let Some(beneath) = &self.beneath {
+            if (beneath.map.width, beneath.map.height) != (self.width, self.height) {
+                bail!(
+                    "Layer beneath is {}x{} under a {}x{} map",
+                    beneath.map.width,
+                    beneath.map.height,
+                    self.width,
+                    self.height
+                );
+            }
+            beneath.map.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Iterates every cell in row-major order together with its coord.
+    pub fn iter(&self) -> impl Iterator<Item = (Coord, &Tile)> {
+        self.tiles
+            .iter()
+            .enumerate()
+            .map(|(index, tile)| (self.coord(index), tile))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "outside")]
+    fn test_out_of_range_coord_does_not_alias_the_next_row() {
+        let map = Map::new(4, 3).expect("tile data should load");
+        map.get_tile(Coord::new(0, 4));
+    }
+
+    #[test]
+    fn test_layer_above_follows_the_tiles_beneath() {
+        let mut terrain = Map::new(2, 1).expect("tile data should load");
+        terrain
+            .get_tile_mut(Coord::new(0, 0))
+            .reset_domain_to(TileType::Grass.mask());
+        terrain
+            .get_tile_mut(Coord::new(0, 1))
+            .reset_domain_to(TileType::River.mask());
+
+        let mut tile_data = terrain.tile_data.clone();
+        tile_data.tiles = TileType::Tree.mask() | TileType::Reeds.mask();
+        let mut rules = VerticalRules::new();
+        rules.allow(TileType::Grass, TileType::Tree.mask());
+        rules.allow(
+            TileType::River,
+            TileType::Reeds.mask() | TileType::Rock.mask(),
+        );
+
+        let layer = terrain.layer_above(tile_data.clone(), rules).unwrap();
+        assert_eq!(
+            layer.get_tile(Coord::new(0, 0)).tile_type(),
+            Some(TileType::Tree)
+        );
+        assert_eq!(
+            layer.get_tile(Coord::new(0, 1)).tile_type(),
+            Some(TileType::Reeds)
+        );
+        assert_eq!(
+            layer
+                .beneath()
+                .unwrap()
+                .get_tile(Coord::new(0, 1))
+                .tile_type(),
+            Some(TileType::River)
+        );
+
+        let unsolved = Map::new(2, 1).expect("tile data should load");
+        assert!(
+            unsolved
+                .layer_above(tile_data, VerticalRules::new())
+                .is_err()
+        );
     }
 }
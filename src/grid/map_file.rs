@@ -0,0 +1,318 @@
+use super::tile_data::{Domain, TileData};
+use super::{Coord, Map, Tile};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+/// Bumped whenever the layout of either format changes.
+pub const FORMAT_VERSION: u32 = 1;
+
+const BINARY_MAGIC: [u8; 4] = *b"WFCM";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MapFormat {
+    Json,
+    /// Magic, version, width, height and tileset hash, followed by one
+    /// domain bitmask per cell, all little-endian.
+    Binary,
+}
+
+#[derive(Debug)]
+pub enum MapFileError {
+    NotAMapFile,
+    UnsupportedVersion {
+        found: u32,
+    },
+    /// The map was generated with different tiles or adjacency rules.
+    TilesetMismatch {
+        expected: u64,
+        found: u64,
+    },
+    /// The dimensions do not fit the format or overflow the cell count.
+    TooLarge {
+        width: usize,
+        height: usize,
+    },
+    CellCount {
+        expected: usize,
+        found: usize,
+    },
+    /// A cell's domain is empty or holds tiles the tileset does not have.
+    InvalidDomain {
+        coord: Coord,
+        domain: Domain,
+    },
+}
+
+impl fmt::Display for MapFileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MapFileError::NotAMapFile => write!(f, "Not a map file"),
+            MapFileError::UnsupportedVersion { found } => write!(
+                f,
+                "Map file version {} is not supported (expected {})",
+                found, FORMAT_VERSION
+            ),
+            MapFileError::TilesetMismatch { expected, found } => write!(
+                f,
+                "Map was saved for tileset {:016x}, but the loaded tileset is {:016x}",
+                found, expected
+            ),
+            MapFileError::TooLarge { width, height } => {
+                write!(f, "Map dimensions {}x{} are too large", width, height)
+            }
+            MapFileError::CellCount { expected, found } => {
+                write!(f, "Expected {} cells, found {}", expected, found)
+            }
+            MapFileError::InvalidDomain { coord, domain } => write!(
+                f,
+                "Invalid domain {:#x} at tile ({}, {})",
+                domain.0, coord.row, coord.col
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MapFileError {}
+
+/// On-disk representation of a solved or partially solved map.
+#[derive(Serialize, Deserialize, Debug)]
+struct MapFile {
+    version: u32,
+    width: usize,
+    height: usize,
+    tileset_hash: u64,
+    cells: Vec<Tile>,
+}
+
+impl Map {
+    pub fn save(&self, path: impl AsRef<Path>, format: MapFormat) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.write_to(&mut writer, format)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load(path: impl AsRef<Path>, format: MapFormat, tile_data: TileData) -> Result<Self> {
+        let reader = BufReader::new(File::open(path)?);
+        Self::read_from(reader, format, tile_data)
+    }
+
+    pub fn write_to(&self, mut writer: impl Write, format: MapFormat) -> Result<()> {
+        let file = MapFile {
+            version: FORMAT_VERSION,
+            width: self.width,
+            height: self.height,
+            tileset_hash: self.tile_data.fingerprint(),
+            cells: self.tiles().to_vec(),
+        };
+
+        match format {
+            MapFormat::Json => serde_json::to_writer_pretty(writer, &file)?,
+            MapFormat::Binary => {
+                writer.write_all(&BINARY_MAGIC)?;
+                writer.write_all(&file.version.to_le_bytes())?;
+                let too_large = || MapFileError::TooLarge {
+                    width: file.width,
+                    height: file.height,
+                };
+                let width = u32::try_from(file.width).map_err(|_| too_large())?;
+                let height = u32::try_from(file.height).map_err(|_| too_large())?;
+                writer.write_all(&width.to_le_bytes())?;
+                writer.write_all(&height.to_le_bytes())?;
+                writer.write_all(&file.tileset_hash.to_le_bytes())?;
+                for tile in &file.cells {
+                    writer.write_all(&tile.current_domain.0.to_le_bytes())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads a map saved with `write_to`, checking it against `tile_data`.
+    pub fn read_from(
+        mut reader: impl Read,
+        format: MapFormat,
+        tile_data: TileData,
+    ) -> Result<Self> {
+        let file = match format {
+            MapFormat::Json => serde_json::from_reader(reader)?,
+            MapFormat::Binary => {
+                let mut magic = [0; 4];
+                reader.read_exact(&mut magic)?;
+                if magic != BINARY_MAGIC {
+                    return Err(MapFileError::NotAMapFile.into());
+                }
+
+                let version = read_u32(&mut reader)?;
+                if version != FORMAT_VERSION {
+                    return Err(MapFileError::UnsupportedVersion { found: version }.into());
+                }
+
+                let width = read_u32(&mut reader)? as usize;
+                let height = read_u32(&mut reader)? as usize;
+                let mut hash = [0; 8];
+                reader.read_exact(&mut hash)?;
+
+                let mut bytes = Vec::new();
+                reader.read_to_end(&mut bytes)?;
+                let cells = bytes
+                    .chunks(4)
+                    .map(|word| {
+                        let word = <[u8; 4]>::try_from(word)
+                            .map_err(|_| anyhow::anyhow!("Truncated map file"))?;
+                        Ok(Tile::new(Domain(u32::from_le_bytes(word))))
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+
+                MapFile {
+                    version,
+                    width,
+                    height,
+                    tileset_hash: u64::from_le_bytes(hash),
+                    cells,
+                }
+            }
+        };
+
+        file.validate(&tile_data)?;
+        Ok(Map::from_tiles(
+            file.width,
+            file.height,
+            tile_data,
+            file.cells,
+        ))
+    }
+}
+
+impl MapFile {
+    fn validate(&self, tile_data: &TileData) -> Result<(), MapFileError> {
+        if self.version != FORMAT_VERSION {
+            return Err(MapFileError::UnsupportedVersion {
+                found: self.version,
+            });
+        }
+
+        let expected = tile_data.fingerprint();
+        if self.tileset_hash != expected {
+            return Err(MapFileError::TilesetMismatch {
+                expected,
+                found: self.tileset_hash,
+            });
+        }
+
+        let cell_count = self
+            .width
+            .checked_mul(self.height)
+            .ok_or(MapFileError::TooLarge {
+                width: self.width,
+                height: self.height,
+            })?;
+        if self.cells.len() != cell_count {
+            return Err(MapFileError::CellCount {
+                expected: cell_count,
+                found: self.cells.len(),
+            });
+        }
+
+        for (index, tile) in self.cells.iter().enumerate() {
+            let domain = tile.current_domain;
+            if domain.is_empty() || !domain.difference(tile_data.tiles).is_empty() {
+                return Err(MapFileError::InvalidDomain {
+                    coord: Coord::from_index(index, self.width),
+                    domain,
+                });
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::TileType;
+
+    fn sample_map() -> Map {
+        let mut map = Map::new(5, 3).expect("tile data should load");
+        map.get_tile_mut(Coord::new(1, 2))
+            .reset_domain_to(TileType::Grass.mask());
+        map.get_tile_mut(Coord::new(2, 4))
+            .reset_domain_to(TileType::Beach.mask() | TileType::Desert.mask());
+        map
+    }
+
+    fn round_trip(map: &Map, format: MapFormat) -> Result<Map> {
+        let mut bytes = Vec::new();
+        map.write_to(&mut bytes, format)?;
+        Map::read_from(bytes.as_slice(), format, map.tile_data.clone())
+    }
+
+    #[test]
+    fn test_round_trip_preserves_domains() {
+        let map = sample_map();
+
+        for format in [MapFormat::Json, MapFormat::Binary] {
+            let loaded = round_trip(&map, format).unwrap();
+            assert_eq!((loaded.width, loaded.height), (5, 3));
+            assert_eq!(loaded.tiles(), map.tiles());
+        }
+    }
+
+    #[test]
+    fn test_rejects_other_tileset() {
+        let map = sample_map();
+        let mut bytes = Vec::new();
+        map.write_to(&mut bytes, MapFormat::Binary).unwrap();
+
+        let mut other = map.tile_data.clone();
+        other.tiles.remove_tile(TileType::Snow);
+
+        let error = Map::read_from(bytes.as_slice(), MapFormat::Binary, other).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MapFileError>(),
+            Some(MapFileError::TilesetMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_rejects_empty_domain() {
+        let mut map = sample_map();
+        map.get_tile_mut(Coord::new(0, 3))
+            .reset_domain_to(Domain::empty());
+
+        let error = round_trip(&map, MapFormat::Json).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MapFileError>(),
+            Some(MapFileError::InvalidDomain { coord, .. }) if *coord == Coord::new(0, 3)
+        ));
+    }
+
+    #[test]
+    fn test_rejects_overflowing_dimensions() {
+        let tile_data = sample_map().tile_data;
+        let file = MapFile {
+            version: FORMAT_VERSION,
+            width: usize::MAX,
+            height: 2,
+            tileset_hash: tile_data.fingerprint(),
+            cells: Vec::new(),
+        };
+
+        assert!(matches!(
+            file.validate(&tile_data),
+            Err(MapFileError::TooLarge { .. })
+        ));
+    }
+}
@@ -0,0 +1,115 @@
+use super::tile_data::TileType;
+use super::{Map, ScalarField};
+use anyhow::{Result, bail};
+use std::collections::HashMap;
+
+/// Steps per unit of field value when applying a prior; see
+/// `Map::apply_prior`.
+const PRIOR_LEVELS: u32 = 256;
+
+/// Biases tile choices by a scalar field such as elevation. Each listed tile
+/// prefers one field value, and its weight falls off the further a cell's
+/// value is from it. Only weights change, so adjacency rules still hold.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldPrior {
+    pub preferred: Vec<(TileType, f32)>,
+    /// How quickly weights fall off; larger means sharper bands.
+    pub sharpness: f32,
+}
+
+impl FieldPrior {
+    /// Elevation bands for the bundled tileset: deep water low, then
+    /// shallows, beaches, lowland, mountains and snow at the top.
+    pub fn elevation() -> Self {
+        Self {
+            preferred: vec![
+                (TileType::DeepWater, 0.0),
+                (TileType::ShallowWater, 0.2),
+                (TileType::Beach, 0.3),
+                (TileType::River, 0.35),
+                (TileType::Grass, 0.45),
+                (TileType::Desert, 0.45),
+                (TileType::Forest, 0.55),
+                (TileType::Mountain, 0.75),
+                (TileType::Snow, 1.0),
+            ],
+            sharpness: 12.0,
+        }
+    }
+
+    /// Weight multiplier for `tile_type` at field value `value`.
+    pub fn factor(&self, tile_type: TileType, value: f32) -> f32 {
+        self.preferred
+            .iter()
+            .find(|(preferred, _)| *preferred == tile_type)
+            .map_or(1.0, |(_, target)| {
+                (-(value - target).powi(2) * self.sharpness).exp()
+            })
+    }
+}
+
+impl Map {
+    /// Scales every cell's weights by `prior` at the cell's value of `field`.
+    /// Field values are rounded to `PRIOR_LEVELS` steps between 0 and 1, so
+    /// cells with the same weights and a similar value share a table.
+    pub fn apply_prior(&mut self, field: &ScalarField, prior: &FieldPrior) -> Result<()> {
+        if (field.width, field.height) != (self.width, self.height) {
+            bail!(
+                "Field is {}x{} but the map is {}x{}",
+                field.width,
+                field.height,
+                self.width,
+                self.height
+            );
+        }
+
+        let mut tables: HashMap<(Option<usize>, i64), usize> = HashMap::new();
+        for index in 0..self.width * self.height {
+            let coord = self.coord(index);
+            let level = (field.get(coord) * PRIOR_LEVELS as f32).round() as i64;
+            let current = self.weight_table(coord);
+
+            let id = match tables.get(&(current, level)) {
+                Some(&id) => id,
+                None => {
+                    let value = level as f32 / PRIOR_LEVELS as f32;
+                    let mut weights = self.weights(coord).copied().unwrap_or_default();
+                    for &(tile_type, _) in &prior.preferred {
+                        weights.scale(tile_type, prior.factor(tile_type, value));
+                    }
+                    let id = self.add_weight_table(weights);
+                    tables.insert((current, level), id);
+                    id
+                }
+            };
+            self.set_weight_table(coord, id)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::grid::Coord;
+
+    #[test]
+    fn test_elevation_favours_water_low_and_snow_high() {
+        let mut map = Map::new(2, 1).expect("tile data should load");
+        let field = ScalarField::from_values(2, 1, vec![0.0, 1.0]).unwrap();
+        map.apply_prior(&field, &FieldPrior::elevation()).unwrap();
+
+        let low = map.weights(Coord::new(0, 0)).unwrap();
+        let high = map.weights(Coord::new(0, 1)).unwrap();
+        assert!(low.get(TileType::DeepWater) > 100.0 * low.get(TileType::Snow));
+        assert!(high.get(TileType::Snow) > 100.0 * high.get(TileType::DeepWater));
+        assert_eq!(low.get(TileType::BeachWaterN), 1.0);
+
+        // Hard constraints are untouched
+        assert_eq!(
+            map.get_tile(Coord::new(0, 0)).current_domain,
+            map.tile_data.tiles
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use super::{Coord, Map};
+use anyhow::{Result, bail};
+
+/// A set of cells of a `Map`, e.g. the area to regenerate when inpainting.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Region {
+    /// `width` x `height` cells starting at `top_left`. Cells past the edge of
+    /// the map are ignored.
+    Rect {
+        top_left: Coord,
+        width: usize,
+        height: usize,
+    },
+    /// One flag per cell of the map, row-major like `Map` itself.
+    Mask(Vec<bool>),
+}
+
+impl Region {
+    pub fn rect(top_left: Coord, width: usize, height: usize) -> Self {
+        Region::Rect {
+            top_left,
+            width,
+            height,
+        }
+    }
+
+    pub fn contains(&self, coord: Coord, map_width: usize) -> bool {
+        match self {
+            Region::Rect {
+                top_left,
+                width,
+                height,
+            } => {
+                // Offsets rather than end points, which could overflow
+                coord.row >= top_left.row
+                    && coord.row - top_left.row < *height
+                    && coord.col >= top_left.col
+                    && coord.col - top_left.col < *width
+            }
+            Region::Mask(cells) => cells
+                .get(coord.to_index(map_width))
+                .copied()
+                .unwrap_or(false),
+        }
+    }
+
+    /// Coords of every cell of `map` inside the region, in row-major order.
+    pub fn cells(&self, map: &Map) -> Result<Vec<Coord>> {
+        if let Region::Mask(cells) = self
+            && cells.len() != map.width * map.height
+        {
+            bail!(
+                "Mask has {} cells but the map is {}x{}",
+                cells.len(),
+                map.width,
+                map.height
+            );
+        }
+
+        Ok(map
+            .iter()
+            .map(|(coord, _)| coord)
+            .filter(|&coord| self.contains(coord, map.width))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rect_reaching_past_usize_max() {
+        let region = Region::rect(Coord::new(2, 3), usize::MAX, usize::MAX);
+
+        assert!(region.contains(Coord::new(2, 3), 10));
+        assert!(region.contains(Coord::new(usize::MAX, usize::MAX), 10));
+        assert!(!region.contains(Coord::new(1, 3), 10));
+        assert!(!region.contains(Coord::new(2, 2), 10));
+    }
+}
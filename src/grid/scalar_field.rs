@@ -0,0 +1,181 @@
+use super::Coord;
+use anyhow::{Context, Result, bail};
+use rand::SeedableRng;
+use rand::seq::SliceRandom;
+use rand_chacha::ChaCha8Rng;
+use std::path::Path;
+
+/// One value in `[0, 1]` per cell, row-major like `Map`, e.g. elevation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScalarField {
+    pub width: usize,
+    pub height: usize,
+    values: Vec<f32>,
+}
+
+impl ScalarField {
+    /// Values outside `[0, 1]` are clamped.
+    pub fn from_values(width: usize, height: usize, values: Vec<f32>) -> Result<Self> {
+        if values.len() != width * height {
+            bail!(
+                "Field has {} values for a {}x{} map",
+                values.len(),
+                width,
+                height
+            );
+        }
+
+        let values = values
+            .into_iter()
+            .map(|value| value.clamp(0.0, 1.0))
+            .collect();
+        Ok(Self {
+            width,
+            height,
+            values,
+        })
+    }
+
+    /// Reads a greyscale heightmap, black being 0 and white 1, sampled at the
+    /// centre of each of the `width` x `height` cells.
+    pub fn from_image(path: impl AsRef<Path>, width: usize, height: usize) -> Result<Self> {
+        let path = path.as_ref();
+        let image = image::open(path)
+            .with_context(|| format!("Failed to load heightmap {}", path.display()))?
+            .into_luma16();
+
+        let values = (0..width * height)
+            .map(|index| {
+                let coord = Coord::from_index(index, width);
+                let x = ((2 * coord.col + 1) * image.width() as usize / (2 * width)) as u32;
+                let y = ((2 * coord.row + 1) * image.height() as usize / (2 * height)) as u32;
+                image.get_pixel(x, y).0[0] as f32 / u16::MAX as f32
+            })
+            .collect();
+
+        Self::from_values(width, height, values)
+    }
+
+    /// Fractal Perlin noise stretched to fill `[0, 1]`. `scale` is the size
+    /// in cells of the largest features; each of the `octaves` adds detail at
+    /// half the size and half the amplitude of the previous one.
+    pub fn noise(width: usize, height: usize, seed: u64, scale: f32, octaves: u32) -> Self {
+        let perlin = Perlin::new(seed);
+
+        let mut values: Vec<f32> = (0..width * height)
+            .map(|index| {
+                let coord = Coord::from_index(index, width);
+                let (mut frequency, mut amplitude) = (1.0 / scale.max(f32::EPSILON), 1.0);
+                let mut value = 0.0;
+
+                for _ in 0..octaves.max(1) {
+                    value += amplitude
+                        * perlin.sample(coord.col as f32 * frequency, coord.row as f32 * frequency);
+                    frequency *= 2.0;
+                    amplitude *= 0.5;
+                }
+                value
+            })
+            .collect();
+
+        let min = values.iter().copied().fold(f32::INFINITY, f32::min);
+        let max = values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let range = max - min;
+        for value in &mut values {
+            *value = if range > 0.0 {
+                (*value - min) / range
+            } else {
+                0.5
+            };
+        }
+
+        Self {
+            width,
+            height,
+            values,
+        }
+    }
+
+    pub fn get(&self, coord: Coord) -> f32 {
+        self.values[coord.to_index(self.width)]
+    }
+}
+
+/// Classic gradient noise over a seeded permutation table.
+struct Perlin {
+    permutation: [u8; 512],
+}
+
+impl Perlin {
+    fn new(seed: u64) -> Self {
+        let mut table: Vec<u8> = (0..=255).collect();
+        table.shuffle(&mut ChaCha8Rng::seed_from_u64(seed));
+
+        let mut permutation = [0; 512];
+        for (index, value) in permutation.iter_mut().enumerate() {
+            *value = table[index % 256];
+        }
+        Self { permutation }
+    }
+
+    fn hash(&self, x: usize, y: usize) -> u8 {
+        self.permutation[self.permutation[x & 255] as usize + (y & 255)]
+    }
+
+    /// Noise at (`x`, `y`), roughly in `[-1, 1]`.
+    fn sample(&self, x: f32, y: f32) -> f32 {
+        let (cell_x, cell_y) = (x.floor(), y.floor());
+        let (dx, dy) = (x - cell_x, y - cell_y);
+        let (ix, iy) = (cell_x as i64 as usize, cell_y as i64 as usize);
+
+        let corner = |ox: usize, oy: usize| {
+            let gradient = self.hash(ix.wrapping_add(ox), iy.wrapping_add(oy));
+            let (px, py) = (dx - ox as f32, dy - oy as f32);
+            match gradient & 3 {
+                0 => px + py,
+                1 => -px + py,
+                2 => px - py,
+                _ => -px - py,
+            }
+        };
+
+        let fade = |t: f32| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let (u, v) = (fade(dx), fade(dy));
+
+        lerp(
+            lerp(corner(0, 0), corner(1, 0), u),
+            lerp(corner(0, 1), corner(1, 1), u),
+            v,
+        ) * 0.5
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_noise_is_seeded_and_normalised() {
+        let field = ScalarField::noise(24, 16, 5, 8.0, 3);
+        assert_eq!(field, ScalarField::noise(24, 16, 5, 8.0, 3));
+        assert_ne!(field, ScalarField::noise(24, 16, 6, 8.0, 3));
+
+        assert!(field.values.iter().all(|value| (0.0..=1.0).contains(value)));
+        assert!(field.values.contains(&0.0) && field.values.contains(&1.0));
+    }
+
+    #[test]
+    fn test_heightmap_is_sampled_per_cell() {
+        let image =
+            image::GrayImage::from_fn(4, 2, |x, _| image::Luma([if x < 2 { 0 } else { 255 }]));
+        let path = std::env::temp_dir().join(format!("wfc-heightmap-{}.png", std::process::id()));
+        image.save(&path).unwrap();
+
+        let field = ScalarField::from_image(&path, 2, 1).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(field.get(Coord::new(0, 0)), 0.0);
+        assert_eq!(field.get(Coord::new(0, 1)), 1.0);
+    }
+}
@@ -1,19 +1,29 @@
 use super::tile_data::{Domain, TileType};
+use super::tile_weights::TileWeights;
 use anyhow::Result;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize, Clone, Debug)]
+/// A single cell of the map. The collapsed tile type is derived from the
+/// domain, so a tile is exactly as large as its `Domain`.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(transparent)]
+#[repr(transparent)]
 pub struct Tile {
-    pub tile_type: Option<TileType>,
     pub current_domain: Domain,
 }
 
 impl Tile {
-    pub fn new(tile_type: Option<TileType>, current_domain: Domain) -> Self {
-        Tile {
-            tile_type,
-            current_domain,
-        }
+    pub fn new(current_domain: Domain) -> Self {
+        Tile { current_domain }
+    }
+
+    pub fn tile_type(&self) -> Option<TileType> {
+        self.current_domain.as_single_tile()
+    }
+
+    pub fn is_collapsed(&self) -> bool {
+        self.current_domain.entropy() == 1
     }
 
     pub fn get_current_domain_size(&self) -> usize {
@@ -33,10 +43,6 @@ impl Tile {
             return None;
         }
 
-        if self.current_domain.entropy() == 1 {
-            self.tile_type = self.current_domain.as_single_tile();
-        }
-
         Some(removed)
     }
 
@@ -44,18 +50,24 @@ impl Tile {
         self.current_domain.remove_tile(tile_type);
     }
 
-    pub fn collapse_self(&mut self) -> Result<(TileType, Domain)> {
+    /// Collapses to one tile of the domain, drawn uniformly or according to
+    /// `weights`. Returns the tile and the tiles removed from the domain.
+    pub fn collapse_self(
+        &mut self,
+        rng: &mut impl Rng,
+        weights: Option<&TileWeights>,
+    ) -> Result<(TileType, Domain)> {
         let mut removed = self.current_domain;
 
-        let collapsed_tile = self
-            .current_domain
-            .collapse_domain()
-            .ok_or_else(|| anyhow::anyhow!("Cannot collapse tile with empty current_domain"))?;
+        let collapsed_tile = match weights {
+            Some(weights) => weights.choose(self.current_domain, rng),
+            None => self.current_domain.collapse_domain(rng),
+        }
+        .ok_or_else(|| anyhow::anyhow!("Cannot collapse tile with empty current_domain"))?;
 
+        self.current_domain = collapsed_tile.mask();
         removed = collapsed_tile.mask() ^ removed;
 
-        self.tile_type = Some(collapsed_tile);
-
         Ok((collapsed_tile, removed))
     }
 }
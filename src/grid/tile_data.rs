@@ -1,4 +1,8 @@
+use super::big_tile::BigTile;
+use super::coord::Direction;
+use super::variant::Variant;
 use anyhow::Result;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::File;
@@ -10,17 +14,36 @@ use std::path::Path;
 pub struct TileDataRaw {
     pub tiles: Vec<TileType>,
     pub supports: HashMap<TileType, TileConstraintsRaw>,
+    #[serde(default)]
+    pub big_tiles: Vec<BigTile>,
+    #[serde(default)]
+    pub variants: HashMap<TileType, Vec<Variant>>,
+    #[serde(default)]
+    pub mirrored: HashMap<TileType, TileType>,
+    #[serde(default)]
+    pub rotated: HashMap<TileType, TileType>,
 }
 
-
-
-#[derive(Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TileData {
     pub tiles: Domain,
     pub supports: HashMap<TileType, TileConstraints>,
+    /// Multi-cell patterns the solver may place, in the order it tries them.
+    #[serde(default)]
+    pub big_tiles: Vec<BigTile>,
+    /// Alternative sprites per tile, for rendering only.
+    #[serde(default)]
+    pub variants: HashMap<TileType, Vec<Variant>>,
+    /// Counterpart of each tile in a map mirrored left to right; see
+    /// `TileData::mirrored`.
+    #[serde(default)]
+    pub mirrored: HashMap<TileType, TileType>,
+    /// Counterpart of each tile in a map turned a quarter turn clockwise;
+    /// see `TileData::rotated`.
+    #[serde(default)]
+    pub rotated: HashMap<TileType, TileType>,
 }
 
-
 impl TileData {
     pub fn load(path: impl AsRef<Path>) -> Result<Self> {
         let file = File::open(path)?;
@@ -43,10 +66,89 @@ impl TileData {
                     })
                     .collect();
         
-        Ok(TileData { tiles, supports })
-}
-}
+        let mut tile_data = TileData {
+            tiles,
+            supports,
+            big_tiles: raw_data.big_tiles,
+            variants: raw_data.variants,
+            mirrored: raw_data.mirrored,
+            rotated: raw_data.rotated,
+        };
+        tile_data.make_symmetric();
+
+        for big_tile in &tile_data.big_tiles {
+            big_tile.validate(&tile_data)?;
+        }
+        tile_data.validate_variants()?;
+        tile_data.validate_counterparts()?;
+        Ok(tile_data)
+    }
+
+    /// Keeps only the adjacencies that both tiles allow. The solver enforces
+    /// the rules of both sides anyway; pruning them up front lets propagation
+    /// see dead ends before a tile is chosen.
+    fn make_symmetric(&mut self) {
+        let original = self.supports.clone();
+
+        for (tile_type, constraints) in self.supports.iter_mut() {
+            for direction in Direction::ALL {
+                let mut allowed = constraints.towards(direction);
+
+                for neighbour in constraints.towards(direction).iter_tiles() {
+                    let allowed_back = original
+                        .get(&neighbour)
+                        .map_or(Domain::empty(), |back| back.towards(direction.opposite()));
+
+                    if allowed_back.intersection(tile_type.mask()).is_empty() {
+                        allowed.remove_tile(neighbour);
+                    }
+                }
+
+                match direction {
+                    Direction::Top => constraints.top = allowed,
+                    Direction::Bottom => constraints.bottom = allowed,
+                    Direction::Right => constraints.right = allowed,
+                    Direction::Left => constraints.left = allowed,
+                }
+            }
+        }
+    }
+
+    /// Stable 64-bit FNV-1a hash of the tile set and its adjacency rules,
+    /// used to check that a saved map belongs to this tileset.
+    pub fn fingerprint(&self) -> u64 {
+        let mut words = vec![self.tiles.0];
+
+        let mut supports: Vec<_> = self.supports.iter().collect();
+        supports.sort_by_key(|(tile_type, _)| **tile_type as u8);
+        for (tile_type, constraints) in supports {
+            words.push(*tile_type as u32);
+            words.extend(Direction::ALL.map(|direction| constraints.towards(direction).0));
+        }
+
+        words
+            .iter()
+            .flat_map(|word| word.to_le_bytes())
+            .fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+            })
+    }
+
+    /// Union of the tiles allowed on the `direction` side of any tile in `domain`.
+    pub fn supported_neighbours(&self, domain: Domain, direction: Direction) -> Result<Domain> {
+        let mut supported = Domain::empty();
 
+        for tile_type in domain.iter_tiles() {
+            let tile_constraints = self.supports.get(&tile_type).ok_or_else(|| {
+                anyhow::anyhow!("Missing constraint data for tile type {:?}", tile_type)
+            })?;
+
+            supported.add_tiles(tile_constraints.towards(direction));
+        }
+
+        Ok(supported)
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TileConstraintsRaw {
@@ -56,8 +158,7 @@ pub struct TileConstraintsRaw {
     pub left: Vec<TileType>,
 }
 
-
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct TileConstraints {
     pub top: Domain,
     pub right: Domain,
@@ -65,6 +166,17 @@ pub struct TileConstraints {
     pub left: Domain,
 }
 
+impl TileConstraints {
+    pub fn towards(&self, direction: Direction) -> Domain {
+        match direction {
+            Direction::Top => self.top,
+            Direction::Bottom => self.bottom,
+            Direction::Right => self.right,
+            Direction::Left => self.left,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 #[serde(rename_all = "snake_case")]
 #[repr(u8)]
@@ -105,9 +217,86 @@ pub enum TileType {
     MountainSnowE = 22,
     MountainSnowS = 23,
     MountainSnowW = 24,
+
+    // Decorations, for a layer above the terrain
+    Bare = 25,
+    Tree = 26,
+    Rock = 27,
+    House = 28,
+    Reeds = 29,
 }
 
 impl TileType {
+    /// Every tile type, in `repr` order.
+    pub const ALL: [TileType; 30] = [
+        TileType::DeepWater,
+        TileType::ShallowWater,
+        TileType::River,
+        TileType::Beach,
+        TileType::Grass,
+        TileType::Forest,
+        TileType::Mountain,
+        TileType::Snow,
+        TileType::Desert,
+        TileType::BeachWaterN,
+        TileType::BeachWaterE,
+        TileType::BeachWaterS,
+        TileType::BeachWaterW,
+        TileType::BeachWaterNe,
+        TileType::BeachWaterNw,
+        TileType::BeachWaterSe,
+        TileType::BeachWaterSw,
+        TileType::GrassForestN,
+        TileType::GrassForestE,
+        TileType::GrassForestS,
+        TileType::GrassForestW,
+        TileType::MountainSnowN,
+        TileType::MountainSnowE,
+        TileType::MountainSnowS,
+        TileType::MountainSnowW,
+        TileType::Bare,
+        TileType::Tree,
+        TileType::Rock,
+        TileType::House,
+        TileType::Reeds,
+    ];
+
+    /// The snake_case name used in tiledata.json and the sprite file names.
+    pub fn name(self) -> &'static str {
+        match self {
+            TileType::DeepWater => "deep_water",
+            TileType::ShallowWater => "shallow_water",
+            TileType::River => "river",
+            TileType::Beach => "beach",
+            TileType::Grass => "grass",
+            TileType::Forest => "forest",
+            TileType::Mountain => "mountain",
+            TileType::Snow => "snow",
+            TileType::Desert => "desert",
+            TileType::BeachWaterN => "beach_water_n",
+            TileType::BeachWaterE => "beach_water_e",
+            TileType::BeachWaterS => "beach_water_s",
+            TileType::BeachWaterW => "beach_water_w",
+            TileType::BeachWaterNe => "beach_water_ne",
+            TileType::BeachWaterNw => "beach_water_nw",
+            TileType::BeachWaterSe => "beach_water_se",
+            TileType::BeachWaterSw => "beach_water_sw",
+            TileType::GrassForestN => "grass_forest_n",
+            TileType::GrassForestE => "grass_forest_e",
+            TileType::GrassForestS => "grass_forest_s",
+            TileType::GrassForestW => "grass_forest_w",
+            TileType::MountainSnowN => "mountain_snow_n",
+            TileType::MountainSnowE => "mountain_snow_e",
+            TileType::MountainSnowS => "mountain_snow_s",
+            TileType::MountainSnowW => "mountain_snow_w",
+            TileType::Bare => "bare",
+            TileType::Tree => "tree",
+            TileType::Rock => "rock",
+            TileType::House => "house",
+            TileType::Reeds => "reeds",
+        }
+    }
+
     pub fn mask(self) -> Domain {
         Domain(1u32 << self as u8)
     }
@@ -139,6 +328,11 @@ impl TileType {
             22 => Some(TileType::MountainSnowE),
             23 => Some(TileType::MountainSnowS),
             24 => Some(TileType::MountainSnowW),
+            25 => Some(TileType::Bare),
+            26 => Some(TileType::Tree),
+            27 => Some(TileType::Rock),
+            28 => Some(TileType::House),
+            29 => Some(TileType::Reeds),
             _ => None,
         }
     }
@@ -151,7 +345,7 @@ impl Domain {
     pub fn is_empty(&self) -> bool {
         self.0 == 0
     }
-    
+
     pub fn from_tiles(tiles: &[TileType]) -> Self{    
         tiles.iter().fold(Domain(0), |acc, tile| {
             acc | tile.mask()
@@ -177,7 +371,7 @@ impl Domain {
 
         let bit_position = self.0.trailing_zeros();
 
-        if bit_position > 24 {
+        if bit_position as usize >= TileType::ALL.len() {
             return None;
         }
 
@@ -188,13 +382,13 @@ impl Domain {
         self.0 &= !tile.mask().0;
     }
 
-    pub fn collapse_domain(&mut self) -> Option<TileType> {
+    pub fn collapse_domain(&mut self, rng: &mut impl Rng) -> Option<TileType> {
         
         if self.entropy() == 0{
             return None;
         }
         
-        let random_index = rand::random_range(0..self.entropy());
+        let random_index = rng.random_range(0..self.entropy());
 
         for _ in 0..random_index {
             self.0 &= self.0 - 1
@@ -219,9 +413,8 @@ impl Domain {
 
         std::iter::from_fn(move || {
             if mask == 0 {
-                return None;
-            } 
-            else {
+                None
+            } else {
                 let index = mask.trailing_zeros();
                 mask &= mask - 1;
                 Some(Domain(1 << index))
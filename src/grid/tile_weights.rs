@@ -0,0 +1,128 @@
+use super::tile_data::{Domain, TileType};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+/// Largest weight a tile can have, small enough that the weights of every
+/// tile type still add up to a finite total.
+pub const MAX_WEIGHT: f32 = f32::MAX / 32.0;
+
+/// Relative likelihood of each tile type being chosen when a cell is
+/// collapsed. Only the ratios between the tiles left in a domain matter.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TileWeights([f32; TileType::ALL.len()]);
+
+impl Default for TileWeights {
+    fn default() -> Self {
+        Self::uniform()
+    }
+}
+
+impl TileWeights {
+    pub fn uniform() -> Self {
+        Self([1.0; TileType::ALL.len()])
+    }
+
+    pub fn get(&self, tile_type: TileType) -> f32 {
+        self.0[tile_type as usize]
+    }
+
+    /// Sets the weight of `tile_type`, clamped to `0..=MAX_WEIGHT`. NaN
+    /// counts as zero.
+    pub fn set(&mut self, tile_type: TileType, weight: f32) {
+        let weight = if weight.is_nan() { 0.0 } else { weight };
+        self.0[tile_type as usize] = weight.clamp(0.0, MAX_WEIGHT);
+    }
+
+    pub fn scale(&mut self, tile_type: TileType, factor: f32) {
+        self.set(tile_type, self.get(tile_type) * factor);
+    }
+
+    /// Picks a tile from `domain` with probability proportional to its
+    /// weight. Falls back to a uniform pick when every tile in the domain
+    /// has weight zero, or the weights do not add up to a finite total.
+    pub fn choose(&self, domain: Domain, rng: &mut impl Rng) -> Option<TileType> {
+        let weighted = || {
+            domain
+                .iter_tiles()
+                .map(|tile_type| (tile_type, self.get(tile_type)))
+        };
+        pick_weighted(weighted, rng).or_else(|| {
+            let mut uniform = domain;
+            uniform.collapse_domain(rng)
+        })
+    }
+}
+
+/// Picks one of the weighted `choices` with probability proportional to its
+/// weight; `choices` is called once to add up the weights and once to pick.
+/// Returns `None` when the weights do not add up to a positive, finite
+/// total, leaving the fallback to the caller.
+pub(crate) fn pick_weighted<T, I>(choices: impl Fn() -> I, rng: &mut impl Rng) -> Option<T>
+where
+    I: Iterator<Item = (T, f32)>,
+{
+    let total: f32 = choices().map(|(_, weight)| weight).sum();
+    if !(total > 0.0 && total.is_finite()) {
+        return None;
+    }
+
+    let mut target = rng.random_range(0.0..total);
+    let mut last = None;
+    for (choice, weight) in choices() {
+        if weight <= 0.0 {
+            continue;
+        }
+        if target < weight {
+            return Some(choice);
+        }
+        target -= weight;
+        last = Some(choice);
+    }
+
+    // Rounding can leave `target` just past the final weight
+    last
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha8Rng;
+
+    #[test]
+    fn test_choose_skips_zero_weights() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+        let domain = TileType::Grass.mask() | TileType::Forest.mask() | TileType::Desert.mask();
+
+        let mut weights = TileWeights::uniform();
+        weights.set(TileType::Grass, 0.0);
+        weights.set(TileType::Desert, 0.0);
+        for _ in 0..50 {
+            assert_eq!(weights.choose(domain, &mut rng), Some(TileType::Forest));
+        }
+
+        weights.set(TileType::Forest, 0.0);
+        assert!(weights.choose(domain, &mut rng).is_some());
+        assert_eq!(weights.choose(Domain::empty(), &mut rng), None);
+    }
+
+    #[test]
+    fn test_huge_factors_stay_finite() {
+        let mut rng = ChaCha8Rng::seed_from_u64(0);
+
+        let mut weights = TileWeights::uniform();
+        weights.scale(TileType::Grass, f32::INFINITY);
+        weights.scale(TileType::Forest, f32::NAN);
+        assert_eq!(weights.get(TileType::Grass), MAX_WEIGHT);
+        assert_eq!(weights.get(TileType::Forest), 0.0);
+
+        let domain = TileType::Grass.mask() | TileType::Forest.mask();
+        assert_eq!(weights.choose(domain, &mut rng), Some(TileType::Grass));
+
+        for tile_type in TileType::ALL {
+            weights.set(tile_type, f32::INFINITY);
+        }
+        let every_tile = Domain::from_tiles(&TileType::ALL);
+        assert!(weights.choose(every_tile, &mut rng).is_some());
+    }
+}
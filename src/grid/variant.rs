@@ -0,0 +1,110 @@
+use super::coord::Coord;
+use super::tile_data::{TileData, TileType};
+use anyhow::{Result, bail};
+use serde::{Deserialize, Serialize};
+
+/// Weight of anything weighted that leaves it out, as in tiledata.json.
+pub(crate) fn default_weight() -> f32 {
+    1.0
+}
+
+/// Mixes `z` into a well distributed 64-bit hash, e.g. to derive stable
+/// per-cell or per-chunk values from a seed.
+pub(crate) fn splitmix64(mut z: u64) -> u64 {
+    z = z.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+/// An alternative sprite for a tile. Variants only change how a tile is
+/// drawn; the solver never sees them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Variant {
+    /// Drawn from `tile_<sprite>.png`.
+    pub sprite: String,
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+}
+
+impl TileData {
+    /// Sprite name to draw `tile_type` with at `coord`. The variant is picked
+    /// by weight from a hash of `seed` and `coord`, so a map renders the same
+    /// every time; tiles without variants use their own name.
+    pub fn sprite(&self, tile_type: TileType, coord: Coord, seed: u64) -> &str {
+        let Some(variants) = self.variants.get(&tile_type) else {
+            return tile_type.name();
+        };
+
+        let hash = [coord.row as u64, coord.col as u64, tile_type as u64]
+            .into_iter()
+            .fold(splitmix64(seed), |hash, value| splitmix64(hash ^ value));
+        // Top 53 bits as a uniform fraction in [0, 1)
+        let fraction = (hash >> 11) as f64 / (1u64 << 53) as f64;
+
+        let total: f64 = variants.iter().map(|variant| variant.weight as f64).sum();
+        let mut target = fraction * total;
+        for variant in variants {
+            target -= variant.weight as f64;
+            if target < 0.0 {
+                return &variant.sprite;
+            }
+        }
+        &variants[variants.len() - 1].sprite
+    }
+
+    pub(super) fn validate_variants(&self) -> Result<()> {
+        for (tile_type, variants) in &self.variants {
+            if variants.iter().any(|variant| variant.weight < 0.0)
+                || variants.iter().all(|variant| variant.weight == 0.0)
+            {
+                bail!(
+                    "Variants of {} need non-negative weights, at least one above zero",
+                    tile_type.name()
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variants_are_stable_and_weighted() {
+        let mut tile_data = TileData::load("assets/tiledata.json").unwrap();
+        tile_data.variants.insert(
+            TileType::Grass,
+            vec![
+                Variant {
+                    sprite: String::from("grass"),
+                    weight: 3.0,
+                },
+                Variant {
+                    sprite: String::from("earth"),
+                    weight: 1.0,
+                },
+            ],
+        );
+
+        let coords: Vec<Coord> = (0..40)
+            .flat_map(|row| (0..40).map(move |col| Coord::new(row, col)))
+            .collect();
+        let sprites: Vec<&str> = coords
+            .iter()
+            .map(|&coord| tile_data.sprite(TileType::Grass, coord, 7))
+            .collect();
+
+        let again: Vec<&str> = coords
+            .iter()
+            .map(|&coord| tile_data.sprite(TileType::Grass, coord, 7))
+            .collect();
+        assert_eq!(sprites, again);
+
+        let earth = sprites.iter().filter(|&&sprite| sprite == "earth").count();
+        assert!((300..500).contains(&earth), "{} of 1600 were earth", earth);
+        assert_eq!(tile_data.sprite(TileType::Snow, coords[5], 7), "snow");
+    }
+}
@@ -0,0 +1,32 @@
+use super::tile_data::{Domain, TileType};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Which tiles of a layer may sit on each tile of the layer beneath it, e.g.
+/// reeds only on water. See `Map::layer_above`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct VerticalRules {
+    above: HashMap<TileType, Domain>,
+}
+
+impl VerticalRules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows `tiles` on top of `beneath`, besides the tiles already allowed.
+    pub fn allow(&mut self, beneath: TileType, tiles: Domain) {
+        self.above
+            .entry(beneath)
+            .or_insert_with(Domain::empty)
+            .add_tiles(tiles);
+    }
+
+    /// Tiles allowed on top of `beneath`; empty unless some were allowed.
+    pub fn allowed_on(&self, beneath: TileType) -> Domain {
+        self.above
+            .get(&beneath)
+            .copied()
+            .unwrap_or_else(Domain::empty)
+    }
+}
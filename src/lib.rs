@@ -1,6 +1,12 @@
 pub mod bucket_queue;
+pub mod constraints;
+pub mod export;
 pub mod grid;
 pub mod wfc;
+pub mod world;
 
-pub use grid::{Coord, Direction, Map, Tile, TileConstraints, TileData, TileType};
-pub use wfc::{Action, CollapseKind, VisualEvent, WFCState};
+pub use grid::{
+    Coord, Direction, Map, MapFormat, Region, Tile, TileConstraints, TileData, TileType,
+};
+pub use wfc::{Action, CollapseKind, SolverEvent, VisualEvent, WFCState};
+pub use world::{Chunk, ChunkCoord, ChunkedWorld};
@@ -1,11 +1,29 @@
+mod config;
 mod visualization;
 
 use bevy::prelude::*;
+use config::{USAGE, ViewerArgs, ViewerConfig};
 use visualization::WFCPlugin;
 
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+
+    let config = match ViewerConfig::load(&args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{:#}", e);
+            std::process::exit(2);
+        }
+    };
+
     App::new()
         .add_plugins(DefaultPlugins)
         .add_plugins(WFCPlugin)
+        .insert_resource(config)
+        .insert_resource(ViewerArgs(args))
         .run();
 }
@@ -1,129 +1,203 @@
+use crate::config::{MAX_CELLS, ViewerArgs, ViewerConfig};
 use bevy::prelude::*;
-use wfc::{Coord, Map, TileType, VisualEvent, WFCState};
+use std::sync::Mutex;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use wfc::{Coord, Map, TileData, VisualEvent, WFCState};
 
 pub struct WFCPlugin;
 
 impl Plugin for WFCPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Startup, setup).add_systems(Update, step);
+        app.add_systems(Startup, setup)
+            .add_systems(Update, (regenerate, show_title, step).chain());
     }
 }
 
+/// Plays back the events of a solver running on its own thread. The solver
+/// runs up to `EVENT_BUFFER` events ahead; the timer paces how quickly its
+/// events are drawn.
 #[derive(Resource)]
 struct WFCVisual {
-    state: WFCState,
+    events: Mutex<Receiver<VisualEvent>>,
+    /// Sprite entity of each cell, indexed like the map's tiles.
+    cells: Vec<Entity>,
+    width: usize,
+    tile_data: TileData,
+    /// Picks sprite variants; see `TileData::sprite`.
+    seed: u64,
     timer: Timer,
     done: bool,
 }
 
-fn tile_path(tile_type: TileType) -> &'static str {
-    match tile_type {
-        // Water types
-        TileType::DeepWater => "tiles/tile_deep_water.png",
-        TileType::ShallowWater => "tiles/tile_shallow_water.png",
-        TileType::River => "tiles/tile_river.png",
-
-        // Land types
-        TileType::Beach => "tiles/tile_beach.png",
-        TileType::Grass => "tiles/tile_grass.png",
-        TileType::Forest => "tiles/tile_forest.png",
-        TileType::Mountain => "tiles/tile_mountain.png",
-        TileType::Snow => "tiles/tile_snow.png",
-        TileType::Desert => "tiles/tile_desert.png",
-
-        // Beach-Water transitions (edges)
-        TileType::BeachWaterN => "tiles/tile_beach_water_n.png",
-        TileType::BeachWaterE => "tiles/tile_beach_water_e.png",
-        TileType::BeachWaterS => "tiles/tile_beach_water_s.png",
-        TileType::BeachWaterW => "tiles/tile_beach_water_w.png",
-
-        // Beach-Water transitions (corners)
-        TileType::BeachWaterNe => "tiles/tile_beach_water_ne.png",
-        TileType::BeachWaterNw => "tiles/tile_beach_water_nw.png",
-        TileType::BeachWaterSe => "tiles/tile_beach_water_se.png",
-        TileType::BeachWaterSw => "tiles/tile_beach_water_sw.png",
-
-        // Grass-Forest transitions
-        TileType::GrassForestN => "tiles/tile_grass_forest_n.png",
-        TileType::GrassForestE => "tiles/tile_grass_forest_e.png",
-        TileType::GrassForestS => "tiles/tile_grass_forest_s.png",
-        TileType::GrassForestW => "tiles/tile_grass_forest_w.png",
-
-        // Mountain-Snow transitions
-        TileType::MountainSnowN => "tiles/tile_mountain_snow_n.png",
-        TileType::MountainSnowE => "tiles/tile_mountain_snow_e.png",
-        TileType::MountainSnowS => "tiles/tile_mountain_snow_s.png",
-        TileType::MountainSnowW => "tiles/tile_mountain_snow_w.png",
-    }
+/// Events the solver may run ahead of playback before it waits for the
+/// viewer to catch up.
+const EVENT_BUFFER: usize = 4096;
+
+/// Cells added or removed along each axis by the + and - keys.
+const SIZE_STEP: usize = 4;
+
+fn tile_path(sprite: &str) -> String {
+    format!("tiles/tile_{}.png", sprite)
+}
+
+/// Moves `state` onto a thread of its own and returns the receiving end of
+/// its events. The channel is bounded, so a large map does not pile up its
+/// whole history in memory while playback is slow; the thread blocks until
+/// playback catches up. The solver stops once the receiver is dropped.
+fn spawn_solver(state: WFCState) -> Receiver<VisualEvent> {
+    let (sender, receiver) = mpsc::sync_channel(EVENT_BUFFER);
+
+    thread::spawn(move || {
+        for event in state {
+            if sender.send(event).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
 }
 
 fn step(
-    mut visual: ResMut<WFCVisual>,
+    visual: Option<ResMut<WFCVisual>>,
     time: Res<Time>,
     asset_server: Res<AssetServer>,
-    mut query: Query<(&Coord, &mut Sprite)>,
+    mut sprites: Query<&mut Sprite>,
 ) {
+    let Some(mut visual) = visual else {
+        return;
+    };
     visual.timer.tick(time.delta());
+    if visual.done {
+        return;
+    }
 
-    if visual.timer.just_finished() && !visual.done {
-        let visual_event = visual.state.next();
-        if visual_event.is_none() {
-            visual.done = true;
-        }
-        if let Some(event) = visual_event {
-            let (coord, tile_type) = match event {
-                VisualEvent::SetTile {
-                    tile_type, coord, ..
-                } => (coord, Some(tile_type)),
-                VisualEvent::UndoTile { coord } => (coord, None),
-            };
-
-            for (query_coord, mut sprite) in &mut query {
-                if query_coord.row == coord.row && query_coord.col == coord.col {
-                    if let Some(tile_type) = tile_type {
-                        sprite.image = asset_server.load(tile_path(tile_type));
-                        sprite.color = Color::WHITE;
-                    } else {
-                        sprite.image = Handle::default();
-                        sprite.color = Color::srgb(0.1, 0.1, 0.1);
-                    }
-                    break;
-                }
+    // Draw as many events as the timer fired this frame, so playback speed
+    // doesn't depend on the frame rate
+    for _ in 0..visual.timer.times_finished_this_tick() {
+        let received = visual
+            .events
+            .lock()
+            .expect("solver channel lock poisoned")
+            .try_recv();
+        let event = match received {
+            Ok(event) => event,
+            Err(TryRecvError::Empty) => break,
+            Err(TryRecvError::Disconnected) => {
+                visual.done = true;
+                break;
             }
+        };
+
+        let (coord, tile_type) = match event {
+            VisualEvent::SetTile {
+                tile_type, coord, ..
+            } => (coord, Some(tile_type)),
+            VisualEvent::UndoTile { coord } => (coord, None),
+        };
+
+        let cell = visual.cells[coord.to_index(visual.width)];
+        let Ok(mut sprite) = sprites.get_mut(cell) else {
+            continue;
+        };
+        if let Some(tile_type) = tile_type {
+            let name = visual.tile_data.sprite(tile_type, coord, visual.seed);
+            sprite.image = asset_server.load(tile_path(name));
+            sprite.color = Color::WHITE;
+        } else {
+            sprite.image = Handle::default();
+            sprite.color = Color::srgb(0.1, 0.1, 0.1);
         }
     }
 }
 
-fn setup(mut commands: Commands) {
+/// Names the size and seed of a newly started map in the window title, so
+/// a random seed can be noted down and replayed with `--seed`.
+fn show_title(visual: Option<Res<WFCVisual>>, mut windows: Query<&mut Window>) {
+    let Some(visual) = visual else {
+        return;
+    };
+    if !visual.is_added() {
+        return;
+    }
+    for mut window in &mut windows {
+        window.title = format!(
+            "WFC {}x{}, seed {}",
+            visual.width,
+            visual.cells.len() / visual.width,
+            visual.seed
+        );
+    }
+}
+
+fn setup(mut commands: Commands, config: Res<ViewerConfig>) {
     commands.spawn(Camera2d);
+    generate(&mut commands, &config);
+}
 
-    let grid_width = 20;
-    let grid_height = 20;
+/// Starts over with a new map when one of the viewer keys is pressed: R
+/// reloads the config with the original command line, keeping the current
+/// one if it is invalid; S picks a new random seed; + and - grow or shrink
+/// the grid by `SIZE_STEP` cells each way, up to `MAX_CELLS` cells.
+fn regenerate(
+    mut commands: Commands,
+    keys: Res<ButtonInput<KeyCode>>,
+    args: Res<ViewerArgs>,
+    mut config: ResMut<ViewerConfig>,
+    cells: Query<Entity, With<Coord>>,
+) {
+    if keys.just_pressed(KeyCode::KeyR) {
+        match ViewerConfig::load(&args.0) {
+            Ok(reloaded) => *config = reloaded,
+            Err(e) => eprintln!("Keeping the current config: {:#}", e),
+        }
+    } else if keys.just_pressed(KeyCode::KeyS) {
+        config.seed = Some(rand::random());
+    } else if keys.any_just_pressed([KeyCode::Equal, KeyCode::NumpadAdd]) {
+        let (width, height) = (config.width + SIZE_STEP, config.height + SIZE_STEP);
+        if width * height > MAX_CELLS {
+            return;
+        }
+        config.width = width;
+        config.height = height;
+    } else if keys.any_just_pressed([KeyCode::Minus, KeyCode::NumpadSubtract]) {
+        config.width = config.width.saturating_sub(SIZE_STEP).max(1);
+        config.height = config.height.saturating_sub(SIZE_STEP).max(1);
+    } else {
+        return;
+    }
+
+    for cell in &cells {
+        commands.entity(cell).despawn();
+    }
+    // Dropping the old receiver stops its solver at its next event
+    commands.remove_resource::<WFCVisual>();
+    generate(&mut commands, &config);
+}
 
-    let map_data = match Map::new(grid_width, grid_height) {
-        Ok(map) => map,
+/// Starts a solver for a new map and spawns its grid of cells.
+fn generate(commands: &mut Commands, config: &ViewerConfig) {
+    let map_data = match TileData::load(&config.tileset) {
+        Ok(tile_data) => Map::with_tile_data(config.width, config.height, tile_data),
         Err(e) => {
-            eprintln!("Failed to create map: {}", e);
+            eprintln!("Failed to load tileset {}: {}", config.tileset.display(), e);
             return;
         }
     };
 
-    commands.insert_resource(WFCVisual {
-        state: WFCState::new(map_data),
-        timer: Timer::from_seconds(0.00001, TimerMode::Repeating),
-        done: false,
-    });
-
-    let cell_size = 30.0;
-    let offset_x = -(grid_width as f32 * cell_size) / 2.0 + cell_size / 2.0;
-    let offset_y = -(grid_height as f32 * cell_size) / 2.0 + cell_size / 2.0;
+    let seed = config.seed.unwrap_or_else(rand::random);
+    let cell_size = config.cell_size;
+    let offset_x = -(config.width as f32 * cell_size) / 2.0 + cell_size / 2.0;
+    let offset_y = -(config.height as f32 * cell_size) / 2.0 + cell_size / 2.0;
 
-    for y in 0..grid_height {
-        for x in 0..grid_width {
-            commands.spawn((
+    let mut cells = Vec::with_capacity(config.width * config.height);
+    for y in 0..config.height {
+        for x in 0..config.width {
+            let cell = commands.spawn((
                 Sprite {
                     color: Color::srgb(0.1, 0.1, 0.1),
-                    custom_size: Some(Vec2::new(28.0, 28.0)),
+                    custom_size: Some(Vec2::splat(config.sprite_size)),
                     ..default()
                 },
                 Transform::from_xyz(
@@ -133,6 +207,18 @@ fn setup(mut commands: Commands) {
                 ),
                 Coord { row: y, col: x },
             ));
+            cells.push(cell.id());
         }
     }
+
+    let tile_data = map_data.tile_data.clone();
+    commands.insert_resource(WFCVisual {
+        events: Mutex::new(spawn_solver(WFCState::with_seed(map_data, seed))),
+        cells,
+        width: config.width,
+        tile_data,
+        seed,
+        timer: Timer::from_seconds(config.interval, TimerMode::Repeating),
+        done: false,
+    });
 }
@@ -1,5 +1,7 @@
+mod events;
 mod history;
 mod wfc_state;
 
+pub use events::{Observer, SolverEvent};
 pub use history::{Action, CollapseKind, VisualEvent};
-pub use wfc_state::WFCState;
+pub use wfc_state::{Contradiction, Solutions, SolveError, WFCState};
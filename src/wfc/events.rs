@@ -0,0 +1,66 @@
+use super::history::CollapseKind;
+use crate::grid::{Coord, Domain, TileType};
+use serde::{Deserialize, Serialize};
+use std::sync::mpsc::{self, Receiver};
+
+/// Everything the solver does, in the order it happens. Replaying the
+/// `DomainReduced` and `DomainRestored` events over the initial map yields
+/// the current domains.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SolverEvent {
+    /// A cell was left with a single tile, either chosen by the solver
+    /// (`Explicit`) or forced by propagation (`Implicit`).
+    Collapsed {
+        coord: Coord,
+        tile_type: TileType,
+        kind: CollapseKind,
+    },
+    DomainReduced {
+        coord: Coord,
+        removed: Domain,
+        entropy: usize,
+    },
+    /// Tiles given back to a cell while backtracking.
+    DomainRestored {
+        coord: Coord,
+        restored: Domain,
+        entropy: usize,
+    },
+    /// The domain at `coord` became empty.
+    Contradiction {
+        coord: Coord,
+    },
+    /// A global constraint can no longer be satisfied.
+    ConstraintViolated {
+        constraint: String,
+    },
+    /// Emitted after a contradiction. Unless solving fails, a matching
+    /// `BacktrackFinished` or `BigTileBanned` follows once a consistent
+    /// state is restored.
+    BacktrackStarted,
+    /// `banned` was removed from `coord`, and solving continues from there.
+    BacktrackFinished {
+        coord: Coord,
+        banned: TileType,
+    },
+    /// The big tile anchored at `anchor` was taken back and may not be
+    /// placed there again; the anchor keeps its other options.
+    BigTileBanned {
+        anchor: Coord,
+        name: String,
+    },
+    Solved,
+}
+
+/// Callback registered with `WFCState::subscribe`.
+pub type Observer = Box<dyn FnMut(&SolverEvent) + Send + Sync>;
+
+/// Wraps a channel sender as an observer; events are dropped once the
+/// receiver is gone.
+pub(super) fn channel_observer() -> (Observer, Receiver<SolverEvent>) {
+    let (sender, receiver) = mpsc::channel();
+    let observer = Box::new(move |event: &SolverEvent| {
+        let _ = sender.send(event.clone());
+    });
+    (observer, receiver)
+}
@@ -1,12 +1,13 @@
 use crate::grid::{Coord, Domain, TileType};
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum CollapseKind {
     Explicit,
     Implicit,
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Action {
     Collapse {
         kind: CollapseKind,
@@ -19,8 +20,24 @@ pub enum Action {
         removed: Domain,
         current_entropy: usize,
     },
+    /// Follows the explicit collapse of the anchor; the rest of the
+    /// footprint collapses implicitly after it.
+    PlaceBigTile { anchor: Coord, big_tile: usize },
+    /// Rules out a big tile at `anchor` after its placement failed.
+    BanBigTile { anchor: Coord, big_tile: usize },
 }
 
+impl Action {
+    /// The cell the action applies to; the anchor for big tiles.
+    pub fn coord(&self) -> Coord {
+        match *self {
+            Action::Collapse { coord, .. } | Action::DomainReduction { coord, .. } => coord,
+            Action::PlaceBigTile { anchor, .. } | Action::BanBigTile { anchor, .. } => anchor,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
 pub enum VisualEvent {
     SetTile { tile_type: TileType, coord: Coord },
     UndoTile { coord: Coord },
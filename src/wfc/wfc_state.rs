@@ -1,15 +1,35 @@
+use super::events::{Observer, SolverEvent, channel_observer};
 use super::history::{Action, CollapseKind, VisualEvent};
 use crate::bucket_queue::BucketQueue;
-use crate::grid::TileType;
-use crate::grid::{Coord, Direction, Domain, Map};
-use anyhow::{Result, anyhow};
-use std::collections::{VecDeque};
+use crate::constraints::{GlobalConstraint, Propagation};
+use crate::grid::{Coord, Domain, Map, Region, TileType};
+use anyhow::{Result, bail};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
 use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::mpsc::Receiver;
 
 #[derive(Debug)]
 pub enum Contradiction {
-    EmptyDomain { tile_type: TileType, coord: Coord },
-    ExhaustedPaths { tile_type: TileType, coord: Coord },
+    /// Propagation removed the last tile type, `tile_type`, from the domain
+    /// at `coord`.
+    EmptyDomain {
+        tile_type: TileType,
+        coord: Coord,
+    },
+    /// Ruling out `tile_type` at `coord` would leave it no tile type at all.
+    ExhaustedPaths {
+        tile_type: TileType,
+        coord: Coord,
+    },
+    ConstraintViolated {
+        constraint: String,
+    },
 }
 
 impl fmt::Display for Contradiction {
@@ -29,38 +49,140 @@ impl fmt::Display for Contradiction {
                     coord.row, coord.col, tile_type
                 )
             }
+            Contradiction::ConstraintViolated { constraint } => {
+                write!(
+                    f,
+                    "Contradiction - {} can no longer be satisfied",
+                    constraint
+                )
+            }
         }
     }
 }
 
 impl std::error::Error for Contradiction {}
 
+#[derive(Debug)]
+pub enum SolveError {
+    /// Every alternative was tried; the map has no solution.
+    Unsatisfiable,
+    BacktrackLimitReached {
+        limit: usize,
+    },
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SolveError::Unsatisfiable => {
+                write!(f, "No explicit collapse left to backtrack to")
+            }
+            SolveError::BacktrackLimitReached { limit } => {
+                write!(f, "Gave up after {} backtracks", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SolveError {}
+
+/// Everything the solver needs to continue, RNG position included, so a
+/// snapshot resumes with exactly the choices the original run would make.
+#[derive(Serialize, Deserialize)]
 pub struct WFCState {
     map: Map,
     least_entropy: BucketQueue,
     timeline: VecDeque<VisualEvent>,
     history: Vec<Action>,
+    rng: ChaCha8Rng,
+    backtrack_limit: Option<usize>,
+    backtracks: usize,
+    /// Explicit collapses taken back with `undo_last_collapse`, latest last,
+    /// with the big tile they placed, if any.
+    #[serde(default)]
+    redo_stack: Vec<(Coord, TileType, Option<usize>)>,
+    /// Big tiles ruled out at an anchor by backtracking.
+    #[serde(default)]
+    banned_big_tiles: Vec<(Coord, usize)>,
+    #[serde(skip)]
+    observers: Vec<Observer>,
+    #[serde(skip)]
+    constraints: Vec<Box<dyn GlobalConstraint>>,
+    /// How many of `constraints` have seen the whole map; see
+    /// `GlobalConstraint::propagate`.
+    #[serde(skip)]
+    primed_constraints: usize,
+    /// Cells whose domains changed since the constraints last ran.
+    #[serde(skip)]
+    touched: Vec<Coord>,
 }
 
 impl Iterator for WFCState {
     type Item = VisualEvent;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.timeline.pop_front().or_else(|| {
+        loop {
+            if let Some(event) = self.timeline.pop_front() {
+                return Some(event);
+            }
+
             self.least_entropy.peek_min()?;
-            match self.collapse() {
-                Ok((tile_type, coord)) => Some(VisualEvent::SetTile { tile_type, coord }),
-                Err(e) => {
-                    eprintln!("WFC Error: {}", e);
-                    None
-                }
+            if let Err(e) = self.collapse() {
+                eprintln!("WFC Error: {}", e);
+                return None;
             }
-        })
+        }
+    }
+}
+
+/// Every completed map reachable from a state, produced by
+/// `WFCState::solutions`.
+pub struct Solutions {
+    state: WFCState,
+    started: bool,
+    done: bool,
+}
+
+impl Iterator for Solutions {
+    type Item = Result<Map>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+
+        match self.state.next_solution(self.started) {
+            Ok(Some(map)) => {
+                self.started = true;
+                Some(Ok(map))
+            }
+            Ok(None) => {
+                self.done = true;
+                None
+            }
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
+            }
+        }
     }
 }
 
 impl WFCState {
+    /// Starts a solver for `map`. Cells that are already collapsed, e.g.
+    /// pinned by the caller, are never queued for collapse and are not part
+    /// of the history, so backtracking never undoes them.
     pub fn new(map: Map) -> Self {
+        Self::with_rng(map, ChaCha8Rng::from_os_rng())
+    }
+
+    /// Like `new`, but every random choice is drawn from `seed`, so the same
+    /// map and seed always produce the same result.
+    pub fn with_seed(map: Map, seed: u64) -> Self {
+        Self::with_rng(map, ChaCha8Rng::seed_from_u64(seed))
+    }
+
+    fn with_rng(map: Map, rng: ChaCha8Rng) -> Self {
         let least_entropy = WFCState::set_initial_entropy(&map);
 
         WFCState {
@@ -68,19 +190,440 @@ impl WFCState {
             least_entropy,
             timeline: VecDeque::new(),
             history: Vec::new(),
+            rng,
+            backtrack_limit: None,
+            backtracks: 0,
+            redo_stack: Vec::new(),
+            banned_big_tiles: Vec::new(),
+            observers: Vec::new(),
+            constraints: Vec::new(),
+            primed_constraints: 0,
+            touched: Vec::new(),
         }
     }
 
-    fn set_initial_entropy(map: &Map) -> BucketQueue {
-        let mut queue = BucketQueue::new(map.tile_data.tiles.entropy() as usize);
-
-        for (row_idx, row) in map.tiles.iter().enumerate() {
-            for (col_idx, tile) in row.iter().enumerate() {
-                let coord = Coord::new(row_idx, col_idx);
-                let entropy = tile.get_current_domain_size();
-                if let Err(insert) = queue.insert(coord, entropy) {
-                    panic!("Failed to Insert: {:?}", insert);
+    /// Prepares a solver that regenerates `region` of a solved map. Cells
+    /// outside the region are already collapsed and never queued, so they
+    /// keep their tiles; if nothing fits between them, solving fails instead.
+    pub fn inpaint(mut map: Map, region: &Region, seed: u64) -> Result<Self> {
+        let mut inside = vec![false; map.tiles().len()];
+        for coord in region.cells(&map)? {
+            inside[coord.to_index(map.width)] = true;
+        }
+        if let Some((coord, _)) = map
+            .iter()
+            .find(|(coord, tile)| !tile.is_collapsed() && !inside[coord.to_index(map.width)])
+        {
+            bail!(
+                "Cannot inpaint an unsolved map: ({}, {}) is outside the region and not collapsed",
+                coord.row,
+                coord.col
+            );
+        }
+
+        map.reset_region(region)?;
+        let mut state = Self::with_seed(map, seed);
+        state.propagate_all()?;
+        Ok(state)
+    }
+
+    /// Writes the complete solver state as JSON; see `resume`.
+    pub fn snapshot(&self, writer: impl Write) -> Result<()> {
+        serde_json::to_writer(writer, self)?;
+        Ok(())
+    }
+
+    pub fn resume(reader: impl Read) -> Result<Self> {
+        let state: Self = serde_json::from_reader(reader)?;
+
+        let map = &state.map;
+        map.validate()?;
+
+        state.least_entropy.validate(
+            map.tile_data.tiles.entropy() as usize,
+            map.width,
+            map.height,
+        )?;
+
+        let big_tiles = map.tile_data.big_tiles.len();
+        let history = state.history.iter().map(|action| {
+            let big_tile = match *action {
+                Action::PlaceBigTile { big_tile, .. } | Action::BanBigTile { big_tile, .. } => {
+                    Some(big_tile)
                 }
+                _ => None,
+            };
+            (action.coord(), big_tile)
+        });
+        let redo = state
+            .redo_stack
+            .iter()
+            .map(|&(coord, _, big_tile)| (coord, big_tile));
+        let banned = state
+            .banned_big_tiles
+            .iter()
+            .map(|&(coord, big_tile)| (coord, Some(big_tile)));
+
+        for (coord, big_tile) in history.chain(redo).chain(banned) {
+            if coord.row >= map.height || coord.col >= map.width {
+                bail!(
+                    "Snapshot refers to ({}, {}) outside the {}x{} map",
+                    coord.row,
+                    coord.col,
+                    map.width,
+                    map.height
+                );
+            }
+            if let Some(big_tile) = big_tile.filter(|&index| index >= big_tiles) {
+                bail!(
+                    "Snapshot refers to big tile {} but the tileset has {}",
+                    big_tile,
+                    big_tiles
+                );
+            }
+        }
+
+        Ok(state)
+    }
+
+    pub fn save_snapshot(&self, path: impl AsRef<Path>) -> Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        self.snapshot(&mut writer)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    pub fn load_snapshot(path: impl AsRef<Path>) -> Result<Self> {
+        Self::resume(BufReader::new(File::open(path)?))
+    }
+
+    /// Adds a rule over the whole map that is enforced during propagation,
+    /// or returns an error if the rule does not fit the map. Like observers,
+    /// constraints are not part of snapshots and have to be added again
+    /// after `resume`.
+    pub fn add_constraint(&mut self, constraint: impl GlobalConstraint + 'static) -> Result<()> {
+        constraint.validate(&self.map)?;
+        self.constraints.push(Box::new(constraint));
+        Ok(())
+    }
+
+    /// Calls `observer` with every `SolverEvent` from now on. Observers are
+    /// not part of snapshots.
+    pub fn subscribe(&mut self, observer: impl FnMut(&SolverEvent) + Send + Sync + 'static) {
+        self.observers.push(Box::new(observer));
+    }
+
+    /// Like `subscribe`, but delivers the events over a channel.
+    pub fn subscribe_channel(&mut self) -> Receiver<SolverEvent> {
+        let (observer, receiver) = channel_observer();
+        self.observers.push(observer);
+        receiver
+    }
+
+    fn emit(&mut self, event: SolverEvent) {
+        for observer in &mut self.observers {
+            observer(&event);
+        }
+    }
+
+    pub fn get_map(&self) -> &Map {
+        &self.map
+    }
+
+    pub fn into_map(self) -> Map {
+        self.map
+    }
+
+    /// Caps how many times the solver may backtrack before giving up with
+    /// `SolveError::BacktrackLimitReached`.
+    pub fn set_backtrack_limit(&mut self, limit: Option<usize>) {
+        self.backtrack_limit = limit;
+    }
+
+    pub fn is_solved(&self) -> bool {
+        self.least_entropy.peek_min().is_none()
+    }
+
+    /// Propagates the constraints of every cell, pruning tiles that cannot
+    /// fit next to their neighbours' domains. Fails if cells restricted before
+    /// solving started contradict each other.
+    pub fn propagate_all(&mut self) -> Result<()> {
+        let mut stack: Vec<Coord> = self.map.iter().map(|(coord, _)| coord).collect();
+        self.propagate(&mut stack)?;
+
+        if self.is_solved() {
+            self.emit(SolverEvent::Solved);
+        }
+        Ok(())
+    }
+
+    /// Runs the solver to completion without producing visual events.
+    pub fn solve(&mut self) -> Result<()> {
+        while !self.is_solved() {
+            self.collapse()?;
+            self.timeline.clear();
+        }
+        Ok(())
+    }
+
+    /// Enumerates every valid completion of the map. The search always picks
+    /// the lowest tile of the cell with the least entropy and moves on by
+    /// banning the last choice, so the order is deterministic and the RNG is
+    /// not used. Any backtrack limit is lifted.
+    pub fn solutions(mut self) -> Solutions {
+        self.backtrack_limit = None;
+        Solutions {
+            state: self,
+            started: false,
+            done: false,
+        }
+    }
+
+    /// Counts the solutions, stopping once `cap` have been found.
+    pub fn count_solutions(self, cap: Option<usize>) -> Result<usize> {
+        let mut count = 0;
+        for solution in self.solutions() {
+            solution?;
+            count += 1;
+            if cap.is_some_and(|cap| count >= cap) {
+                break;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Searches for the next solution; with `resume`, the current solved
+    /// state is rejected first. Returns `None` once the search is exhausted.
+    fn next_solution(&mut self, resume: bool) -> Result<Option<Map>> {
+        let searched = if resume {
+            self.backtrack()
+        } else {
+            self.propagate_all()
+        };
+
+        match searched {
+            Ok(()) => {}
+            Err(e) if Self::search_exhausted(&e) => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        while !self.is_solved() {
+            match self.collapse_lowest() {
+                Ok(()) => {}
+                Err(e) if Self::search_exhausted(&e) => return Ok(None),
+                Err(e) => return Err(e),
+            }
+            self.timeline.clear();
+        }
+        self.timeline.clear();
+
+        Ok(Some(self.map.clone()))
+    }
+
+    /// Whether `error` only means that no further solution exists.
+    fn search_exhausted(error: &anyhow::Error) -> bool {
+        error.is::<Contradiction>()
+            || matches!(error.downcast_ref(), Some(SolveError::Unsatisfiable))
+    }
+
+    /// Like `collapse`, but always chooses the lowest tile in the domain.
+    fn collapse_lowest(&mut self) -> Result<()> {
+        let Some(coord) = self.find_least_entropy() else {
+            return Ok(());
+        };
+
+        let tile = self.map.get_tile_mut(coord);
+        let Some(tile_type) = tile.current_domain.iter_tiles().next() else {
+            bail!(
+                "Queued cell ({}, {}) has no tiles left",
+                coord.row,
+                coord.col
+            );
+        };
+        let removed = tile.current_domain.difference(tile_type.mask());
+        tile.reset_domain_to(tile_type.mask());
+
+        self.commit_collapse(coord, tile_type, removed, None)
+    }
+
+    /// Number of explicit collapses currently applied; see `rewind_to`.
+    pub fn current_step(&self) -> usize {
+        self.history
+            .iter()
+            .filter(|action| {
+                matches!(
+                    action,
+                    Action::Collapse {
+                        kind: CollapseKind::Explicit,
+                        ..
+                    }
+                )
+            })
+            .count()
+    }
+
+    /// Takes back the most recent explicit collapse and everything it
+    /// implied, queueing `UndoTile` events. Returns false if there was
+    /// nothing to undo. The undone collapse can be reapplied with `redo`.
+    pub fn undo_last_collapse(&mut self) -> Result<bool> {
+        let Some(index) = self.find_last_collapse() else {
+            return Ok(false);
+        };
+        let big_tile = self.placed_big_tile(index);
+        self.undo_until(index + 1)?;
+
+        let Some(Action::Collapse {
+            tile_type,
+            coord,
+            removed,
+            ..
+        }) = self.history.pop()
+        else {
+            unreachable!("find_last_collapse returned a non-collapse action");
+        };
+        self.undo_collapse(coord, removed)?;
+
+        self.redo_stack.push((coord, tile_type, big_tile));
+        Ok(true)
+    }
+
+    /// Reapplies the collapse most recently taken back by
+    /// `undo_last_collapse`. Returns false if there is nothing to redo; any
+    /// collapse made by the solver in between clears the redo stack.
+    pub fn redo(&mut self) -> Result<bool> {
+        let Some((coord, tile_type, big_tile)) = self.redo_stack.pop() else {
+            return Ok(false);
+        };
+
+        let fits = match big_tile {
+            Some(index) => self.map.tile_data.big_tiles[index].fits(&self.map, coord),
+            None => !self
+                .map
+                .get_tile(coord)
+                .current_domain
+                .intersection(tile_type.mask())
+                .is_empty(),
+        };
+        if !fits {
+            bail!(
+                "Cannot redo {:?} at ({}, {}): no longer in its domain",
+                tile_type,
+                coord.row,
+                coord.col
+            );
+        }
+        let tile = self.map.get_tile_mut(coord);
+        let removed = tile.current_domain.difference(tile_type.mask());
+        tile.reset_domain_to(tile_type.mask());
+
+        if self.least_entropy.contains(coord) {
+            self.least_entropy.remove(coord)?;
+        }
+        self.commit_collapse(coord, tile_type, removed, big_tile)?;
+        Ok(true)
+    }
+
+    /// Collapses `coord` to `tile_type` on behalf of the caller. The choice is
+    /// recorded as an explicit collapse, so later contradictions may
+    /// backtrack over it like over the solver's own choices. A choice that
+    /// contradicts the current state immediately is rolled back and returned
+    /// as the `Contradiction` error.
+    pub fn collapse_at(&mut self, coord: Coord, tile_type: TileType) -> Result<()> {
+        self.check_bounds(coord)?;
+        let tile = self.map.get_tile_mut(coord);
+        if tile
+            .current_domain
+            .intersection(tile_type.mask())
+            .is_empty()
+        {
+            bail!(
+                "{:?} is not possible at ({}, {})",
+                tile_type,
+                coord.row,
+                coord.col
+            );
+        }
+        if tile.is_collapsed() {
+            return Ok(());
+        }
+
+        let removed = tile.current_domain.difference(tile_type.mask());
+        tile.reset_domain_to(tile_type.mask());
+
+        let history_len = self.history.len();
+        self.least_entropy.remove(coord)?;
+        self.record_collapse(coord, tile_type, removed);
+        self.propagate_or_roll_back(vec![coord], history_len)
+    }
+
+    /// Removes `tile_type` from the domain at `coord` and propagates the
+    /// change. The ban is recorded as a domain reduction, so backtracking
+    /// past the preceding explicit collapse lifts it. Like `collapse_at`, a
+    /// ban that causes a contradiction is rolled back.
+    pub fn ban(&mut self, coord: Coord, tile_type: TileType) -> Result<()> {
+        self.check_bounds(coord)?;
+        let domain = self.map.get_tile(coord).current_domain;
+        if domain.difference(tile_type.mask()).is_empty() {
+            return Err(Contradiction::ExhaustedPaths { tile_type, coord }.into());
+        }
+
+        let history_len = self.history.len();
+        let mut stack: Vec<Coord> = Vec::new();
+
+        if let Err(e) = self.restrict(coord, !tile_type.mask(), &mut stack) {
+            self.undo_until(history_len)?;
+            return Err(e);
+        }
+        self.propagate_or_roll_back(stack, history_len)
+    }
+
+    fn check_bounds(&self, coord: Coord) -> Result<()> {
+        if !self.map.contains(coord) {
+            bail!(
+                "({}, {}) is outside the {}x{} map",
+                coord.row,
+                coord.col,
+                self.map.width,
+                self.map.height
+            );
+        }
+        Ok(())
+    }
+
+    fn propagate_or_roll_back(&mut self, mut stack: Vec<Coord>, history_len: usize) -> Result<()> {
+        if let Err(e) = self.propagate(&mut stack) {
+            self.undo_until(history_len)?;
+            return Err(e);
+        }
+
+        self.redo_stack.clear();
+        if self.is_solved() {
+            self.emit(SolverEvent::Solved);
+        }
+        Ok(())
+    }
+
+    /// Undoes explicit collapses until only `step` of them remain.
+    pub fn rewind_to(&mut self, step: usize) -> Result<()> {
+        for _ in step..self.current_step() {
+            self.undo_last_collapse()?;
+        }
+        Ok(())
+    }
+
+    fn set_initial_entropy(map: &Map) -> BucketQueue {
+        let mut queue = BucketQueue::new(
+            map.tile_data.tiles.entropy() as usize,
+            map.width,
+            map.height,
+        );
+
+        // Cells that start out collapsed (pinned or kept while inpainting)
+        // have nothing left to choose
+        for (coord, tile) in map.iter().filter(|(_, tile)| !tile.is_collapsed()) {
+            let entropy = tile.get_current_domain_size();
+            if let Err(insert) = queue.insert(coord, entropy) {
+                panic!("Failed to Insert: {:?}", insert);
             }
         }
 
@@ -105,8 +648,86 @@ impl WFCState {
         })
     }
 
-    fn backtrack(&mut self, to: usize) -> anyhow::Result<()> {
-        while self.history.len() > to {
+    /// Big tile placed by the explicit collapse at `index` in the history.
+    fn placed_big_tile(&self, index: usize) -> Option<usize> {
+        match self.history.get(index + 1) {
+            Some(Action::PlaceBigTile { big_tile, .. }) => Some(*big_tile),
+            _ => None,
+        }
+    }
+
+    /// Undoes the most recent explicit collapse and bans the tile it chose,
+    /// or only the big tile if it placed one. The ban is recorded in the
+    /// history, so backtracking past the previous explicit collapse lifts it
+    /// again.
+    fn backtrack(&mut self) -> Result<()> {
+        self.emit(SolverEvent::BacktrackStarted);
+
+        loop {
+            if let Some(limit) = self.backtrack_limit
+                && self.backtracks >= limit
+            {
+                return Err(SolveError::BacktrackLimitReached { limit }.into());
+            }
+            self.backtracks += 1;
+
+            let index = self.find_last_collapse().ok_or(SolveError::Unsatisfiable)?;
+            let big_tile = self.placed_big_tile(index);
+            self.undo_until(index + 1)?;
+
+            let Some(Action::Collapse {
+                tile_type,
+                coord,
+                removed,
+                ..
+            }) = self.history.pop()
+            else {
+                unreachable!("find_last_collapse returned a non-collapse action");
+            };
+            self.undo_collapse(coord, removed)?;
+
+            // The anchor's domain is unchanged, so nothing needs propagating
+            if let Some(big_tile) = big_tile {
+                self.history.push(Action::BanBigTile {
+                    anchor: coord,
+                    big_tile,
+                });
+                self.banned_big_tiles.push((coord, big_tile));
+
+                let name = self.map.tile_data.big_tiles[big_tile].name.clone();
+                self.emit(SolverEvent::BigTileBanned {
+                    anchor: coord,
+                    name,
+                });
+                return Ok(());
+            }
+
+            // The failed tile was the only option left, so go back further
+            if removed.is_empty() {
+                continue;
+            }
+
+            let mut stack: Vec<Coord> = Vec::new();
+            let banned = self
+                .restrict(coord, !tile_type.mask(), &mut stack)
+                .and_then(|()| self.propagate(&mut stack));
+
+            match banned {
+                Ok(()) => {
+                    self.emit(SolverEvent::BacktrackFinished {
+                        coord,
+                        banned: tile_type,
+                    });
+                    return Ok(());
+                }
+                Err(e) if e.is::<Contradiction>() => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn undo_until(&mut self, len: usize) -> Result<()> {
+        while self.history.len() > len {
             match self.history.pop() {
                 Some(Action::Collapse { coord, removed, .. }) => {
                     self.undo_collapse(coord, removed)?
@@ -114,207 +735,737 @@ impl WFCState {
                 Some(Action::DomainReduction { coord, removed, .. }) => {
                     self.undo_domain_reduction(coord, removed)?
                 }
-                _ => continue,
+                Some(Action::PlaceBigTile { anchor, .. }) => self.map.remove_big_tile(anchor),
+                Some(Action::BanBigTile { anchor, big_tile }) => self
+                    .banned_big_tiles
+                    .retain(|&banned| banned != (anchor, big_tile)),
+                None => break,
             }
         }
-
-        // Now undo the target collapse and remove the failed tile type
-        if let Some(Action::Collapse {
-            tile_type,
-            coord,
-            removed,
-            ..
-        }) = self.history.pop()
-        {
-            let tile = self.map.get_tile_mut(coord);
-            tile.tile_type = None;
-            tile.current_domain.add_tiles(removed);
-            tile.remove_contradiction_from_domain(tile_type);
-
-            if tile.get_current_domain_size() == 0 {
-                return Err(Contradiction::ExhaustedPaths { tile_type, coord }.into());
-            }
-
-            let entropy = tile.get_current_domain_size();
-            self.least_entropy.insert(coord, entropy)?;
-            self.timeline.push_back(VisualEvent::UndoTile { coord });
-        }
         Ok(())
     }
 
     fn undo_collapse(&mut self, coord: Coord, removed: Domain) -> anyhow::Result<()> {
+        self.touch(coord);
         let tile = self.map.get_tile_mut(coord);
-        tile.tile_type = None;
         tile.current_domain.add_tiles(removed);
 
         let entropy = tile.get_current_domain_size();
         self.least_entropy.insert(coord, entropy)?;
         self.timeline.push_back(VisualEvent::UndoTile { coord });
+        self.emit(SolverEvent::DomainRestored {
+            coord,
+            restored: removed,
+            entropy,
+        });
         Ok(())
     }
 
     fn undo_domain_reduction(&mut self, coord: Coord, removed: Domain) -> anyhow::Result<()> {
+        self.touch(coord);
         let tile = self.map.get_tile_mut(coord);
         tile.current_domain.add_tiles(removed);
         let entropy = tile.get_current_domain_size();
-        self.least_entropy.update_entropy(coord, entropy)?;
+
+        // Collapsed cells are not queued; their own collapse is undone separately
+        if self.least_entropy.contains(coord) {
+            self.least_entropy.update_entropy(coord, entropy)?;
+        }
+        self.emit(SolverEvent::DomainRestored {
+            coord,
+            restored: removed,
+            entropy,
+        });
         Ok(())
     }
 
-    fn collapse(&mut self) -> anyhow::Result<(TileType, Coord)> {
-        loop {
-            let chosen_cell = self
-                .find_least_entropy()
-                .ok_or_else(|| anyhow::anyhow!("No cells left to collapse"))?;
+    /// Makes one explicit collapse stick, backtracking on contradictions.
+    fn collapse(&mut self) -> Result<()> {
+        let Some(chosen_cell) = self.find_least_entropy() else {
+            return Ok(());
+        };
+        self.redo_stack.clear();
+
+        if let Some(index) = self.choose_big_tile(chosen_cell) {
+            let tile_type = self.map.tile_data.big_tiles[index].cells[0][0];
+            let tile = self.map.get_tile_mut(chosen_cell);
+            let removed = tile.current_domain.difference(tile_type.mask());
+            tile.reset_domain_to(tile_type.mask());
+
+            return self.commit_collapse(chosen_cell, tile_type, removed, Some(index));
+        }
+
+        let (chosen_tile_type, removed) = self.map.collapse_cell(chosen_cell, &mut self.rng)?;
+        self.commit_collapse(chosen_cell, chosen_tile_type, removed, None)
+    }
+
+    /// Rolls each big tile that fits at `anchor` against its chance, in the
+    /// order the tileset lists them, and returns the first that succeeds.
+    fn choose_big_tile(&mut self, anchor: Coord) -> Option<usize> {
+        let big_tiles = &self.map.tile_data.big_tiles;
+
+        (0..big_tiles.len()).find(|&index| {
+            let big_tile = &big_tiles[index];
+            !self.banned_big_tiles.contains(&(anchor, index))
+                && big_tile.fits(&self.map, anchor)
+                && self.rng.random_bool(big_tile.chance as f64)
+        })
+    }
+
+    /// Records the explicit collapse of `coord` to `tile_type`, whose domain
+    /// has already been narrowed by `removed`, and propagates it. With a big
+    /// tile, the rest of its footprint is claimed in the same step, so undoing
+    /// the collapse takes back the whole footprint.
+    fn commit_collapse(
+        &mut self,
+        coord: Coord,
+        tile_type: TileType,
+        removed: Domain,
+        big_tile: Option<usize>,
+    ) -> Result<()> {
+        self.record_collapse(coord, tile_type, removed);
+
+        let mut stack: Vec<Coord> = Vec::new();
+        stack.push(coord);
+
+        let claimed = match big_tile {
+            Some(index) => self.claim_footprint(coord, index, &mut stack),
+            None => Ok(()),
+        };
+
+        match claimed.and_then(|()| self.propagate(&mut stack)) {
+            Ok(()) => {}
+            Err(e) if e.is::<Contradiction>() => self.backtrack()?,
+            Err(e) => return Err(e),
+        }
+
+        if self.is_solved() {
+            self.emit(SolverEvent::Solved);
+        }
+        Ok(())
+    }
+
+    fn claim_footprint(
+        &mut self,
+        anchor: Coord,
+        index: usize,
+        changed_cells: &mut Vec<Coord>,
+    ) -> Result<()> {
+        self.history.push(Action::PlaceBigTile {
+            anchor,
+            big_tile: index,
+        });
+        self.map.place_big_tile(anchor, index);
+
+        let footprint: Vec<_> = self.map.tile_data.big_tiles[index]
+            .footprint(anchor)
+            .skip(1)
+            .collect();
+        for (coord, tile_type) in footprint {
+            self.restrict(coord, tile_type.mask(), changed_cells)?;
+        }
+        Ok(())
+    }
+
+    fn record_collapse(&mut self, coord: Coord, tile_type: TileType, removed: Domain) {
+        self.touch(coord);
+        self.emit(SolverEvent::DomainReduced {
+            coord,
+            removed,
+            entropy: 1,
+        });
+        self.emit(SolverEvent::Collapsed {
+            coord,
+            tile_type,
+            kind: CollapseKind::Explicit,
+        });
+
+        self.timeline
+            .push_back(VisualEvent::SetTile { tile_type, coord });
+
+        self.history.push(Action::Collapse {
+            kind: CollapseKind::Explicit,
+            tile_type,
+            coord,
+            removed,
+        });
+    }
+
+    /// Intersects the domain at `coord` with `allowed`, recording the
+    /// reduction and any implicit collapse it causes.
+    fn restrict(
+        &mut self,
+        coord: Coord,
+        allowed: Domain,
+        changed_cells: &mut Vec<Coord>,
+    ) -> Result<()> {
+        let tile = self.map.get_tile_mut(coord);
+
+        let Some(removed) = tile.update_constraints(allowed) else {
+            return Ok(());
+        };
+
+        let entropy_after_update = tile.get_current_domain_size();
+        let collapsed_to = tile.tile_type();
+
+        self.touch(coord);
+        self.history.push(Action::DomainReduction {
+            coord,
+            removed,
+            current_entropy: entropy_after_update,
+        });
+        self.emit(SolverEvent::DomainReduced {
+            coord,
+            removed,
+            entropy: entropy_after_update,
+        });
+
+        if entropy_after_update == 0 {
+            let tile_type = removed
+                .iter_tiles()
+                .last()
+                .expect("a reduction removes at least one tile");
+            self.emit(SolverEvent::Contradiction { coord });
+            return Err(Contradiction::EmptyDomain { tile_type, coord }.into());
+        }
+
+        changed_cells.push(coord);
 
-            let (chosen_tile_type, removed) = self.map.get_tile_mut(chosen_cell).collapse_self()?;
+        if let Some(tile_type) = collapsed_to {
+            self.least_entropy.remove(coord)?;
 
-            self.timeline.push_back(VisualEvent::SetTile {
-                tile_type: chosen_tile_type,
-                coord: chosen_cell,
+            self.timeline
+                .push_back(VisualEvent::SetTile { tile_type, coord });
+            self.emit(SolverEvent::Collapsed {
+                coord,
+                tile_type,
+                kind: CollapseKind::Implicit,
             });
 
             self.history.push(Action::Collapse {
-                kind: CollapseKind::Explicit,
-                tile_type: chosen_tile_type,
-                coord: chosen_cell,
+                kind: CollapseKind::Implicit,
+                tile_type,
+                coord,
                 removed,
             });
+        } else {
+            self.least_entropy
+                .update_entropy(coord, entropy_after_update)?;
+        }
 
-            let mut stack: Vec<Coord> = Vec::new();
-            stack.push(chosen_cell);
-
-            match self.propagate(chosen_cell, chosen_tile_type, &mut stack) {
-                Ok(()) => return Ok((chosen_tile_type, chosen_cell)),
-                Err(_) => {
-                    let mut to = self
-                        .find_last_collapse()
-                        .map(|index| index + 1) // make it in terms of length
-                        .ok_or_else(|| anyhow!("No Tile Found Go Back To"))?;
-
-                    loop {
-                        match self.backtrack(to) {
-                            Ok(()) => break,
-                            Err(e) => {
-                                if let Some(Contradiction::ExhaustedPaths { .. }) =
-                                    e.downcast_ref::<Contradiction>()
-                                {
-                                    to = self
-                                        .find_last_collapse()
-                                        .ok_or_else(|| anyhow!("No Tile Found Go Back To"))?;
-
-                                    continue;
-                                } else {
-                                    return Err(e);
-                                }
-                            }
-                        }
-                    }
+        Ok(())
+    }
+
+    /// Propagates adjacency from `changed_cells` and then the global
+    /// constraints, until neither narrows any domain further.
+    fn propagate(&mut self, changed_cells: &mut Vec<Coord>) -> Result<()> {
+        loop {
+            self.propagate_adjacency(changed_cells)?;
+
+            for (coord, allowed) in self.propagate_constraints()? {
+                self.restrict(coord, allowed, changed_cells)?;
+            }
+            if changed_cells.is_empty() {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Notes that the domain at `coord` changed, for the constraints.
+    fn touch(&mut self, coord: Coord) {
+        if !self.constraints.is_empty() {
+            self.touched.push(coord);
+        }
+    }
+
+    fn propagate_constraints(&mut self) -> Result<Vec<(Coord, Domain)>> {
+        let touched = std::mem::take(&mut self.touched);
+        let every_cell: Vec<Coord> = if self.primed_constraints < self.constraints.len() {
+            self.map.iter().map(|(coord, _)| coord).collect()
+        } else {
+            Vec::new()
+        };
+
+        // Every constraint sees the changes, even after one is violated,
+        // so none of them misses the cells that backtracking restores
+        let mut restrictions = Vec::new();
+        let mut violated = None;
+        for (index, constraint) in self.constraints.iter_mut().enumerate() {
+            let changed = if index < self.primed_constraints {
+                &touched
+            } else {
+                &every_cell
+            };
+            match constraint.propagate(&self.map, changed) {
+                Propagation::Restrict(cells) => restrictions.extend(cells),
+                Propagation::Violated => {
+                    violated.get_or_insert_with(|| constraint.name());
                 }
             }
         }
+        self.primed_constraints = self.constraints.len();
+
+        if let Some(constraint) = violated {
+            self.emit(SolverEvent::ConstraintViolated {
+                constraint: constraint.clone(),
+            });
+            return Err(Contradiction::ConstraintViolated { constraint }.into());
+        }
+        Ok(restrictions)
     }
 
-    fn propagate(
-        &mut self,
-        chosen_cell: Coord,
-        chosen_tile_type: TileType,
-        changed_cells: &mut Vec<Coord>,
-    ) -> Result<()> {
+    fn propagate_adjacency(&mut self, changed_cells: &mut Vec<Coord>) -> Result<()> {
         while let Some(changed_cell) = changed_cells.pop() {
-            let neighbours = changed_cell.neighbours(self.map.width, self.map.height);
+            let current_tile_types = self.map.get_tile(changed_cell).current_domain;
+            let neighbours = changed_cell.neighbours(self.map.height, self.map.width);
 
+            // Collapsed neighbours are restricted too: a cell can collapse
+            // implicitly while constraints from other cells are still pending
             for (direction, coord) in neighbours.into_iter().flatten() {
-                let current_tile_types =
-                    self.map.tiles[changed_cell.row][changed_cell.col].current_domain;
-
-                let mut all_supported_tile_types = Domain::empty();
-
-                for current_tile_type in current_tile_types.iter_tiles() {
-                    let tile_constraints = self
-                        .map
-                        .tile_data
-                        .supports
-                        .get(&current_tile_type)
-                        .ok_or_else(|| {
-                            anyhow::anyhow!(
-                                "Missing constraint data for tile type {:?}",
-                                current_tile_type
-                            )
-                        })?;
-
-                    let new_constraints = match direction {
-                        Direction::Top => tile_constraints.top,
-                        Direction::Bottom => tile_constraints.bottom,
-                        Direction::Right => tile_constraints.right,
-                        Direction::Left => tile_constraints.left,
-                    };
-
-                    all_supported_tile_types.add_tiles(new_constraints);
-                }
+                let all_supported_tile_types = self
+                    .map
+                    .tile_data
+                    .supported_neighbours(current_tile_types, direction)?;
 
-                let current_tile = self.map.get_tile_mut(coord);
+                self.restrict(coord, all_supported_tile_types, changed_cells)?;
+            }
+        }
 
-                // Skip tiles that are already collapsed
-                if current_tile.tile_type.is_some() {
-                    continue;
-                }
+        Ok(())
+    }
+}
 
-                let entropy_before_update = current_tile.get_current_domain_size();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::constraints::{Amount, TileCount};
+    use crate::grid::{BigTile, Direction, TileWeights};
 
-                let removed_tiles = current_tile.update_constraints(all_supported_tile_types);
+    fn solved(width: usize, height: usize, seed: u64) -> WFCState {
+        let map = Map::new(width, height).expect("tile data should load");
+        let mut state = WFCState::with_seed(map, seed);
+        state.solve().expect("seeded map should solve");
+        state
+    }
 
-                if let Some(removed) = removed_tiles {
-                    let entropy_after_update = current_tile.get_current_domain_size();
+    #[test]
+    fn test_solved_map_respects_adjacency() {
+        let state = solved(24, 16, 3);
+        let map = state.get_map();
 
-                    changed_cells.push(coord);
+        for (coord, tile) in map.iter() {
+            let tile_type = tile.tile_type().expect("every cell should be collapsed");
 
-                    if entropy_after_update == 0 {
-                        self.history.push(Action::DomainReduction {
-                            coord,
-                            removed,
-                            current_entropy: entropy_before_update,
-                        });
-                        return Err(Contradiction::EmptyDomain {
-                            tile_type: chosen_tile_type,
-                            coord: chosen_cell,
-                        }
-                        .into());
-                    }
+            for (direction, neighbour) in coord
+                .neighbours(map.height, map.width)
+                .into_iter()
+                .flatten()
+            {
+                let allowed = map
+                    .tile_data
+                    .supported_neighbours(tile_type.mask(), direction)
+                    .unwrap();
+                let neighbour_domain = map.get_tile(neighbour).current_domain;
+                assert!(
+                    !allowed.intersection(neighbour_domain).is_empty(),
+                    "{:?} at {:?} next to {:?} on its {:?} side",
+                    tile_type,
+                    coord,
+                    neighbour_domain,
+                    direction
+                );
+            }
+        }
+    }
 
-                    self.least_entropy
-                        .update_entropy(coord, entropy_after_update)?;
+    #[test]
+    fn test_inpaint_keeps_surroundings() {
+        let original = solved(16, 16, 1).into_map();
+        let region = Region::rect(Coord::new(4, 6), 7, 5);
 
-                    self.history.push(Action::DomainReduction {
-                        coord,
-                        removed: removed.clone(),
-                        current_entropy: entropy_after_update,
-                    });
+        let mut state = WFCState::inpaint(original.clone(), &region, 9).unwrap();
+        state.solve().unwrap();
+        let inpainted = state.get_map();
 
-                    if entropy_after_update == 1 {
-                        let tile_type = current_tile.tile_type.ok_or_else(|| {
-                            anyhow::anyhow!(
-                                "Cell {:?} collapsed but tile_type is None",
-                                changed_cell
-                            )
-                        })?;
-
-                        self.least_entropy.remove(coord)?;
-
-                        self.timeline
-                            .push_back(VisualEvent::SetTile { tile_type, coord });
-
-                        self.history.push(Action::Collapse {
-                            kind: CollapseKind::Implicit,
-                            tile_type,
-                            coord,
-                            removed,
-                        });
-                    }
+        for (coord, tile) in inpainted.iter() {
+            assert!(tile.is_collapsed());
+            if !region.contains(coord, inpainted.width) {
+                assert_eq!(tile, original.get_tile(coord), "{:?} changed", coord);
+            }
+        }
+    }
+
+    #[test]
+    fn test_inpaint_rejects_mismatched_mask() {
+        let original = solved(8, 8, 1).into_map();
+        let region = Region::Mask(vec![true; 10]);
+
+        assert!(WFCState::inpaint(original, &region, 0).is_err());
+    }
+
+    #[test]
+    fn test_resumed_snapshot_finishes_identically() {
+        let map = Map::new(12, 12).expect("tile data should load");
+        let mut original = WFCState::with_seed(map, 4);
+        for _ in 0..40 {
+            original.next();
+        }
+
+        let mut bytes = Vec::new();
+        original.snapshot(&mut bytes).unwrap();
+        let mut resumed = WFCState::resume(bytes.as_slice()).unwrap();
+
+        assert_eq!(original.history, resumed.history);
+        assert_eq!(original.timeline.len(), resumed.timeline.len());
+
+        original.solve().unwrap();
+        resumed.solve().unwrap();
+        assert_eq!(original.get_map().tiles(), resumed.get_map().tiles());
+    }
+
+    #[test]
+    fn test_resume_rejects_damaged_snapshot() {
+        let map = Map::new(6, 6).expect("tile data should load");
+        let mut state = WFCState::with_seed(map, 4);
+        for _ in 0..5 {
+            state.next();
+        }
+        let mut bytes = Vec::new();
+        state.snapshot(&mut bytes).unwrap();
+        let snapshot: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+
+        let mut stale_queue = snapshot.clone();
+        stale_queue["least_entropy"]["min_bucket"] = 99.into();
+        let mut far_action = snapshot.clone();
+        let action = far_action["history"][0]
+            .as_object_mut()
+            .and_then(|action| action.values_mut().next())
+            .unwrap();
+        action["coord"]["row"] = 6.into();
+        let uniform = serde_json::to_value([TileWeights::uniform()]).unwrap();
+        let mut short_weights = snapshot.clone();
+        short_weights["map"]["weight_ids"] = vec![0; 35].into();
+        short_weights["map"]["weight_tables"] = uniform.clone();
+        let mut missing_table = snapshot.clone();
+        missing_table["map"]["weight_ids"] = vec![1; 36].into();
+        missing_table["map"]["weight_tables"] = uniform;
+        let mut far_big_tile = snapshot.clone();
+        far_big_tile["map"]["big_tiles"] = serde_json::json!([[{ "row": 6, "col": 0 }, 0]]);
+        let mut unknown_big_tile = snapshot.clone();
+        unknown_big_tile["map"]["big_tiles"] = serde_json::json!([[{ "row": 0, "col": 0 }, 99]]);
+
+        for damaged in [
+            stale_queue,
+            far_action,
+            short_weights,
+            missing_table,
+            far_big_tile,
+            unknown_big_tile,
+        ] {
+            let bytes = serde_json::to_vec(&damaged).unwrap();
+            assert!(WFCState::resume(bytes.as_slice()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_events_replay_to_the_solved_map() {
+        let map = Map::new(16, 16).expect("tile data should load");
+        let mut domains: Vec<Domain> = map.tiles().iter().map(|tile| tile.current_domain).collect();
+
+        let mut state = WFCState::with_seed(map, 3);
+        let events = state.subscribe_channel();
+        let (sender, solved) = std::sync::mpsc::channel();
+        state.subscribe(move |event| {
+            if *event == SolverEvent::Solved {
+                sender.send(()).unwrap();
+            }
+        });
+        state.solve().unwrap();
+
+        let mut collapses = 0;
+        for event in events.try_iter() {
+            match event {
+                SolverEvent::DomainReduced { coord, removed, .. } => {
+                    domains[coord.to_index(16)] = domains[coord.to_index(16)].difference(removed)
                 }
+                SolverEvent::DomainRestored {
+                    coord, restored, ..
+                } => domains[coord.to_index(16)].add_tiles(restored),
+                SolverEvent::Collapsed { .. } => collapses += 1,
+                _ => {}
             }
         }
 
-        Ok(())
+        let solved_domains: Vec<Domain> = state
+            .get_map()
+            .tiles()
+            .iter()
+            .map(|tile| tile.current_domain)
+            .collect();
+        assert_eq!(domains, solved_domains);
+        assert!(collapses >= 16 * 16);
+        assert_eq!(solved.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn test_undo_redo_and_rewind() {
+        let map = Map::new(10, 10).expect("tile data should load");
+        let initial = map.clone();
+        let mut state = WFCState::with_seed(map, 6);
+
+        for _ in 0..5 {
+            state.collapse().unwrap();
+        }
+        let step = state.current_step();
+        let before_undo = state.get_map().clone();
+        state.timeline.clear();
+
+        assert!(state.undo_last_collapse().unwrap());
+        assert_eq!(state.current_step(), step - 1);
+        assert!(
+            state
+                .timeline
+                .iter()
+                .any(|event| matches!(event, VisualEvent::UndoTile { .. }))
+        );
+
+        assert!(state.redo().unwrap());
+        assert!(!state.redo().unwrap());
+        assert_eq!(state.current_step(), step);
+        assert_eq!(state.get_map().tiles(), before_undo.tiles());
+
+        state.rewind_to(0).unwrap();
+        assert_eq!(state.current_step(), 0);
+        assert_eq!(state.get_map().tiles(), initial.tiles());
+        assert!(!state.undo_last_collapse().unwrap());
+    }
+
+    #[test]
+    fn test_collapse_at_and_ban_go_through_propagation() {
+        let map = Map::new(6, 6).expect("tile data should load");
+        let mut state = WFCState::with_seed(map, 1);
+        let coord = Coord::new(2, 3);
+
+        state.collapse_at(coord, TileType::DeepWater).unwrap();
+        assert_eq!(
+            state.get_map().get_tile(coord).tile_type(),
+            Some(TileType::DeepWater)
+        );
+        assert_eq!(state.current_step(), 1);
+
+        let neighbour = state.get_map().get_tile(Coord::new(2, 4)).current_domain;
+        assert!(neighbour.intersection(TileType::Snow.mask()).is_empty());
+        assert!(state.collapse_at(Coord::new(2, 4), TileType::Snow).is_err());
+
+        state.ban(Coord::new(0, 0), TileType::Grass).unwrap();
+        let corner = state.get_map().get_tile(Coord::new(0, 0)).current_domain;
+        assert!(corner.intersection(TileType::Grass.mask()).is_empty());
+
+        state.solve().unwrap();
+        assert_eq!(
+            state.get_map().get_tile(coord).tile_type(),
+            Some(TileType::DeepWater)
+        );
+    }
+
+    #[test]
+    fn test_collapse_at_and_ban_reject_coords_outside_the_map() {
+        let map = Map::new(4, 3).expect("tile data should load");
+        let mut state = WFCState::with_seed(map, 1);
+
+        assert!(
+            state
+                .collapse_at(Coord::new(3, 0), TileType::Grass)
+                .is_err()
+        );
+        assert!(
+            state
+                .collapse_at(Coord::new(0, 4), TileType::Grass)
+                .is_err()
+        );
+        assert!(state.ban(Coord::new(0, 4), TileType::Grass).is_err());
+        assert_eq!(state.current_step(), 0);
+    }
+
+    #[test]
+    fn test_contradicting_ban_is_rolled_back() {
+        let map = Map::new(4, 4).expect("tile data should load");
+        let mut state = WFCState::with_seed(map, 1);
+        let coord = Coord::new(1, 1);
+        state.collapse_at(coord, TileType::Forest).unwrap();
+        let before = state.get_map().clone();
+
+        let error = state.ban(coord, TileType::Forest).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(Contradiction::ExhaustedPaths {
+                tile_type: TileType::Forest,
+                ..
+            })
+        ));
+        assert_eq!(state.get_map().tiles(), before.tiles());
+        assert_eq!(state.current_step(), 1);
+    }
+
+    #[test]
+    fn test_counts_every_adjacent_pair() {
+        let map = Map::new(2, 1).expect("tile data should load");
+        let tile_data = map.tile_data.clone();
+        let expected: usize = tile_data
+            .tiles
+            .iter_tiles()
+            .map(|tile_type| {
+                tile_data
+                    .supported_neighbours(tile_type.mask(), Direction::Bottom)
+                    .unwrap()
+                    .intersection(tile_data.tiles)
+                    .entropy() as usize
+            })
+            .sum();
+
+        let solutions: Vec<Map> = WFCState::new(map)
+            .solutions()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(solutions.len(), expected);
+
+        let mut seen: Vec<Vec<u32>> = solutions
+            .iter()
+            .map(|map| {
+                map.tiles()
+                    .iter()
+                    .map(|tile| tile.current_domain.0)
+                    .collect()
+            })
+            .collect();
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen.len(), expected);
+    }
+
+    #[test]
+    fn test_count_respects_cap_and_pins() {
+        let map = Map::new(4, 4).expect("tile data should load");
+        assert_eq!(
+            WFCState::new(map.clone())
+                .count_solutions(Some(25))
+                .unwrap(),
+            25
+        );
+
+        let mut pinned = map.clone();
+        pinned
+            .get_tile_mut(Coord::new(0, 0))
+            .reset_domain_to(TileType::DeepWater.mask());
+        pinned
+            .get_tile_mut(Coord::new(0, 1))
+            .reset_domain_to(TileType::Snow.mask());
+        assert_eq!(WFCState::new(pinned).count_solutions(None).unwrap(), 0);
+
+        let mut solved = WFCState::with_seed(map, 2);
+        solved.solve().unwrap();
+        let solved = solved.into_map();
+        assert_eq!(WFCState::new(solved).count_solutions(None).unwrap(), 1);
+    }
+
+    #[test]
+    fn test_count_with_constraint_that_exhausts_the_search() {
+        // A column has no room for two; a row only fits them around a beach
+        for (width, height, expected) in [(1, 3, 0), (3, 1, 1)] {
+            let map = Map::new(width, height).expect("tile data should load");
+            let mut state = WFCState::new(map);
+            state
+                .add_constraint(TileCount::at_least(TileType::BeachWaterN, Amount::Cells(2)))
+                .unwrap();
+
+            assert_eq!(state.count_solutions(None).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_tile_counts_hold_in_solved_map() {
+        let map = Map::new(10, 10).expect("tile data should load");
+        let mut state = WFCState::with_seed(map, 8);
+        state
+            .add_constraint(TileCount::at_most(TileType::Grass, Amount::Cells(0)))
+            .unwrap();
+        state
+            .add_constraint(TileCount::between(
+                TileType::Mountain,
+                Amount::Cells(5),
+                Amount::Fraction(0.1),
+            ))
+            .unwrap();
+        state.solve().unwrap();
+
+        let count = |tile_type: TileType| {
+            state
+                .get_map()
+                .iter()
+                .filter(|(_, tile)| tile.tile_type() == Some(tile_type))
+                .count()
+        };
+        assert_eq!(count(TileType::Grass), 0);
+        assert!((5..=10).contains(&count(TileType::Mountain)));
+    }
+
+    #[test]
+    fn test_same_seed_gives_same_map() {
+        let first = solved(12, 12, 11);
+        let second = solved(12, 12, 11);
+
+        assert_eq!(first.get_map().tiles(), second.get_map().tiles());
+    }
+
+    #[test]
+    fn test_big_tiles_claim_and_release_their_footprint() {
+        let mut map = Map::new(10, 10).expect("tile data should load");
+        map.tile_data.big_tiles.push(BigTile {
+            name: String::from("lake"),
+            cells: vec![
+                vec![TileType::ShallowWater; 3],
+                vec![
+                    TileType::ShallowWater,
+                    TileType::DeepWater,
+                    TileType::ShallowWater,
+                ],
+                vec![TileType::ShallowWater; 3],
+            ],
+            chance: 1.0,
+        });
+        map.tile_data.big_tiles.push(BigTile {
+            name: String::from("castle"),
+            cells: vec![vec![TileType::Mountain; 2]; 2],
+            chance: 1.0,
+        });
+
+        let mut state = WFCState::with_seed(map, 2);
+        while state.get_map().big_tiles().count() == 0 {
+            state.next().expect("a big tile should fit somewhere");
+        }
+        let (anchor, big_tile) = state
+            .get_map()
+            .big_tiles()
+            .map(|(anchor, big_tile)| (anchor, big_tile.clone()))
+            .next()
+            .unwrap();
+        for (coord, tile_type) in big_tile.footprint(anchor) {
+            assert_eq!(state.get_map().get_tile(coord).tile_type(), Some(tile_type));
+        }
+
+        assert!(state.undo_last_collapse().unwrap());
+        assert_eq!(state.get_map().big_tiles().count(), 0);
+        for (coord, _) in big_tile.footprint(anchor) {
+            assert!(!state.get_map().get_tile(coord).is_collapsed());
+        }
+
+        state.redo().unwrap();
+        state.solve().unwrap();
+        let map = state.get_map();
+        for (anchor, big_tile) in map.big_tiles() {
+            for (coord, tile_type) in big_tile.footprint(anchor) {
+                assert_eq!(map.get_tile(coord).tile_type(), Some(tile_type));
+            }
+        }
     }
 }
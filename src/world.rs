@@ -0,0 +1,9 @@
+mod chunked_world;
+mod hierarchical;
+mod labels;
+mod layered;
+
+pub use chunked_world::{CHUNK_SIZE, Chunk, ChunkCoord, ChunkError, ChunkedWorld};
+pub use hierarchical::{HierarchicalMap, HierarchyConfig, MacroTile};
+pub use labels::LabelMap;
+pub use layered::{Decoration, DecorationLayer};
@@ -0,0 +1,381 @@
+use crate::grid::{Coord, Direction, Map, TileData, TileType, splitmix64};
+use crate::wfc::{SolveError, WFCState};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::fmt;
+
+pub const CHUNK_SIZE: usize = 32;
+
+/// How many differently seeded attempts a chunk gets before giving up.
+const MAX_ATTEMPTS: u32 = 32;
+
+/// Backtracks allowed per attempt; restarting with a new seed is usually
+/// cheaper than backtracking out of a bad early decision.
+const BACKTRACK_LIMIT: usize = 200;
+
+/// Cells solved around each chunk. Where a neighbour exists they are pinned
+/// to its tiles; elsewhere they are solved as look-ahead and thrown away, so
+/// that later chunks can continue the chunk's open edges.
+const MARGIN: usize = 4;
+
+#[derive(Debug)]
+pub enum ChunkError {
+    /// The fixed borders of the neighbouring chunks admit no solution.
+    UnsatisfiableBorder {
+        chunk: ChunkCoord,
+    },
+    AttemptsExhausted {
+        chunk: ChunkCoord,
+        attempts: u32,
+    },
+}
+
+impl fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChunkError::UnsatisfiableBorder { chunk } => write!(
+                f,
+                "Borders of the chunks around ({}, {}) cannot be satisfied",
+                chunk.x, chunk.y
+            ),
+            ChunkError::AttemptsExhausted { chunk, attempts } => write!(
+                f,
+                "Chunk ({}, {}) failed to generate after {} attempts",
+                chunk.x, chunk.y, attempts
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ChunkError {}
+
+/// Position of a chunk in chunk units. `y` grows downwards, like `Coord::row`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ChunkCoord {
+    pub x: i64,
+    pub y: i64,
+}
+
+impl ChunkCoord {
+    pub fn new(x: i64, y: i64) -> Self {
+        Self { x, y }
+    }
+
+    /// The chunk containing the world cell at (`row`, `col`).
+    pub fn containing(row: i64, col: i64) -> Self {
+        let size = CHUNK_SIZE as i64;
+        Self::new(col.div_euclid(size), row.div_euclid(size))
+    }
+
+    /// World (row, col) of the chunk's top-left cell.
+    pub fn origin(self) -> (i64, i64) {
+        let size = CHUNK_SIZE as i64;
+        (self.y * size, self.x * size)
+    }
+
+    pub fn neighbour(self, direction: Direction) -> Self {
+        match direction {
+            Direction::Top => Self::new(self.x, self.y - 1),
+            Direction::Bottom => Self::new(self.x, self.y + 1),
+            Direction::Left => Self::new(self.x - 1, self.y),
+            Direction::Right => Self::new(self.x + 1, self.y),
+        }
+    }
+}
+
+/// A fully solved chunk, stored row-major like `Map`.
+#[derive(Debug, Clone)]
+pub struct Chunk {
+    pub coord: ChunkCoord,
+    tiles: Vec<TileType>,
+}
+
+impl Chunk {
+    pub fn get(&self, coord: Coord) -> TileType {
+        self.tiles[coord.to_index(CHUNK_SIZE)]
+    }
+
+    /// Cuts the chunk out of a solved map that has `MARGIN` cells around it.
+    fn from_solved_map(coord: ChunkCoord, map: &Map) -> Result<Self> {
+        let tiles = (0..CHUNK_SIZE * CHUNK_SIZE)
+            .map(|index| {
+                let local = Coord::from_index(index, CHUNK_SIZE);
+                let cell = Coord::new(local.row + MARGIN, local.col + MARGIN);
+                map.get_tile(cell).tile_type()
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| anyhow::anyhow!("Solved chunk has uncollapsed cells"))?;
+
+        Ok(Self { coord, tiles })
+    }
+}
+
+/// An unbounded world generated one `CHUNK_SIZE`² chunk at a time.
+///
+/// New chunks are constrained by the fixed cells of whichever neighbours
+/// already exist, so the tiles of a chunk depend on the order chunks are
+/// requested in. The random choices of each attempt are derived from the
+/// world seed and the chunk coordinates alone.
+pub struct ChunkedWorld {
+    tile_data: TileData,
+    seed: u64,
+    chunks: HashMap<ChunkCoord, Chunk>,
+}
+
+impl ChunkedWorld {
+    pub fn new(tile_data: TileData, seed: u64) -> Self {
+        Self {
+            tile_data,
+            seed,
+            chunks: HashMap::new(),
+        }
+    }
+
+    pub fn get_chunk(&self, coord: ChunkCoord) -> Option<&Chunk> {
+        self.chunks.get(&coord)
+    }
+
+    pub fn chunks(&self) -> impl Iterator<Item = &Chunk> {
+        self.chunks.values()
+    }
+
+    /// The tile at world cell (`row`, `col`), if its chunk has been generated.
+    pub fn tile_at(&self, row: i64, col: i64) -> Option<TileType> {
+        let chunk = self.chunks.get(&ChunkCoord::containing(row, col))?;
+        let size = CHUNK_SIZE as i64;
+        let local = Coord::new(row.rem_euclid(size) as usize, col.rem_euclid(size) as usize);
+        Some(chunk.get(local))
+    }
+
+    pub fn chunk_seed(&self, chunk: ChunkCoord, attempt: u32) -> u64 {
+        [chunk.x as u64, chunk.y as u64, attempt as u64]
+            .into_iter()
+            .fold(splitmix64(self.seed), |seed, value| {
+                splitmix64(seed ^ value)
+            })
+    }
+
+    /// Generates every missing chunk within `radius` chunks of the viewer at
+    /// world cell (`row`, `col`), nearest first. Returns the new chunks' coords.
+    pub fn generate_around(&mut self, row: i64, col: i64, radius: i64) -> Result<Vec<ChunkCoord>> {
+        let centre = ChunkCoord::containing(row, col);
+
+        let mut missing: Vec<ChunkCoord> = (-radius..=radius)
+            .flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+            .map(|(dx, dy)| ChunkCoord::new(centre.x + dx, centre.y + dy))
+            .filter(|coord| !self.chunks.contains_key(coord))
+            .collect();
+        missing.sort_by_key(|coord| {
+            let distance = (coord.x - centre.x).abs().max((coord.y - centre.y).abs());
+            (distance, coord.y, coord.x)
+        });
+
+        for &coord in &missing {
+            self.generate_chunk(coord)?;
+        }
+
+        Ok(missing)
+    }
+
+    /// Generates the chunk at `coord` unless it already exists.
+    pub fn generate_chunk(&mut self, coord: ChunkCoord) -> Result<&Chunk> {
+        if !self.chunks.contains_key(&coord) {
+            let chunk = self.solve_chunk(coord)?;
+            self.chunks.insert(coord, chunk);
+        }
+        Ok(&self.chunks[&coord])
+    }
+
+    /// Solves the chunk with the margin cells of its neighbours pinned. If
+    /// the pinned cells admit no solution, the outermost pinned ring is
+    /// released and the chunk is retried, down to the single ring touching
+    /// the chunk, which is all the seams need to line up.
+    fn solve_chunk(&self, coord: ChunkCoord) -> Result<Chunk> {
+        let mut attempt = 0;
+
+        for depth in (1..=MARGIN).rev() {
+            let map = self.seeded_map(coord, depth);
+
+            match self.solve_attempts(coord, &map, &mut attempt)? {
+                Some(chunk) => return Ok(chunk),
+                None => continue,
+            }
+        }
+
+        Err(ChunkError::UnsatisfiableBorder { chunk: coord }.into())
+    }
+
+    /// Tries differently seeded solves of `map` until one succeeds or the
+    /// attempts run out. Returns `None` if the pinned cells cannot be
+    /// satisfied at all.
+    fn solve_attempts(
+        &self,
+        coord: ChunkCoord,
+        map: &Map,
+        attempt: &mut u32,
+    ) -> Result<Option<Chunk>> {
+        while *attempt < MAX_ATTEMPTS {
+            let mut state = WFCState::with_seed(map.clone(), self.chunk_seed(coord, *attempt));
+            state.set_backtrack_limit(Some(BACKTRACK_LIMIT));
+            *attempt += 1;
+
+            if state.propagate_all().is_err() {
+                return Ok(None);
+            }
+
+            match state.solve() {
+                Ok(()) => return Chunk::from_solved_map(coord, state.get_map()).map(Some),
+                Err(e) => match e.downcast_ref::<SolveError>() {
+                    Some(SolveError::BacktrackLimitReached { .. }) => continue,
+                    Some(SolveError::Unsatisfiable) => return Ok(None),
+                    None => return Err(e),
+                },
+            }
+        }
+
+        Err(ChunkError::AttemptsExhausted {
+            chunk: coord,
+            attempts: MAX_ATTEMPTS,
+        }
+        .into())
+    }
+
+    /// A fresh map covering the chunk plus `MARGIN` cells on every side, with
+    /// the margin cells within `depth` of the chunk that fall in generated
+    /// chunks pinned to their tiles.
+    fn seeded_map(&self, coord: ChunkCoord, depth: usize) -> Map {
+        let size = CHUNK_SIZE + 2 * MARGIN;
+        let mut map = Map::with_tile_data(size, size, self.tile_data.clone());
+
+        let (origin_row, origin_col) = coord.origin();
+        let margin = MARGIN as i64;
+        // Distance of a local row or column from the chunk's own cells
+        let distance = |index: usize| {
+            MARGIN
+                .saturating_sub(index)
+                .max(index.saturating_sub(MARGIN + CHUNK_SIZE - 1))
+        };
+
+        for row in 0..size {
+            for col in 0..size {
+                if distance(row).max(distance(col)) > depth {
+                    continue;
+                }
+
+                let world_row = origin_row - margin + row as i64;
+                let world_col = origin_col - margin + col as i64;
+
+                if let Some(tile_type) = self.tile_at(world_row, world_col) {
+                    map.get_tile_mut(Coord::new(row, col))
+                        .reset_domain_to(tile_type.mask());
+                }
+            }
+        }
+
+        map
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn world(seed: u64) -> ChunkedWorld {
+        let tile_data = TileData::load("assets/tiledata.json").expect("tile data should load");
+        ChunkedWorld::new(tile_data, seed)
+    }
+
+    #[test]
+    fn test_containing_handles_negative_cells() {
+        assert_eq!(ChunkCoord::containing(0, 0), ChunkCoord::new(0, 0));
+        assert_eq!(ChunkCoord::containing(-1, 31), ChunkCoord::new(0, -1));
+        assert_eq!(ChunkCoord::containing(64, -33), ChunkCoord::new(-2, 2));
+    }
+
+    #[test]
+    fn test_neighbouring_chunks_agree_on_seams() {
+        let mut world = world(7);
+        world.generate_around(0, 0, 1).unwrap();
+
+        let size = CHUNK_SIZE as i64;
+        for row in -size..2 * size {
+            for col in -size..2 * size {
+                let tile_type = world.tile_at(row, col).unwrap();
+
+                for (direction, neighbour) in [
+                    (Direction::Right, world.tile_at(row, col + 1)),
+                    (Direction::Bottom, world.tile_at(row + 1, col)),
+                ] {
+                    let Some(neighbour) = neighbour else {
+                        continue;
+                    };
+                    let allowed = world
+                        .tile_data
+                        .supported_neighbours(tile_type.mask(), direction)
+                        .unwrap();
+                    assert!(
+                        !allowed.intersection(neighbour.mask()).is_empty(),
+                        "{:?} at ({}, {}) next to {:?}",
+                        tile_type,
+                        row,
+                        col,
+                        neighbour
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_conflicting_outer_border_is_released() {
+        let mut world = world(3);
+
+        // A neighbour whose second column cannot sit next to its first, so
+        // only the ring touching the chunk can stay pinned
+        let tiles = (0..CHUNK_SIZE * CHUNK_SIZE)
+            .map(|index| match index % CHUNK_SIZE {
+                1 => TileType::Mountain,
+                _ => TileType::DeepWater,
+            })
+            .collect();
+        let right = ChunkCoord::new(1, 0);
+        world.chunks.insert(
+            right,
+            Chunk {
+                coord: right,
+                tiles,
+            },
+        );
+
+        world.generate_chunk(ChunkCoord::new(0, 0)).unwrap();
+
+        let edge = CHUNK_SIZE as i64 - 1;
+        for row in 0..CHUNK_SIZE as i64 {
+            let tile_type = world.tile_at(row, edge).unwrap();
+            let allowed = world
+                .tile_data
+                .supported_neighbours(tile_type.mask(), Direction::Right)
+                .unwrap();
+            assert!(!allowed.intersection(TileType::DeepWater.mask()).is_empty());
+        }
+    }
+
+    #[test]
+    fn test_same_seed_and_order_is_deterministic() {
+        let mut first = world(42);
+        let mut second = world(42);
+
+        let order = [
+            ChunkCoord::new(0, 0),
+            ChunkCoord::new(1, 0),
+            ChunkCoord::new(1, -1),
+        ];
+        for coord in order {
+            let a = first.generate_chunk(coord).unwrap().tiles.clone();
+            let b = second.generate_chunk(coord).unwrap().tiles.clone();
+            assert_eq!(a, b);
+        }
+
+        assert_ne!(first.chunk_seed(order[0], 0), first.chunk_seed(order[1], 0));
+    }
+}
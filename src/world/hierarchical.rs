@@ -0,0 +1,219 @@
+use super::labels::{self, LabelMap, LabelRules, LabelSet};
+use crate::grid::{Biome, BiomeMask, Coord, Domain, Map, TileData, TileType, default_weight};
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// A tile of the coarse level, standing for a square block of fine cells.
+#[derive(Deserialize, Debug, Clone)]
+pub struct MacroTile {
+    pub name: String,
+    /// Fine tiles allowed inside a block of this kind.
+    pub tiles: Vec<TileType>,
+    /// Macro tiles allowed next to this one in any direction. Adjacency is
+    /// symmetric, so listing it on either side is enough.
+    pub neighbours: Vec<String>,
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+}
+
+/// Data for two-level generation, as in assets/hierarchy.json.
+#[derive(Deserialize, Debug, Clone)]
+pub struct HierarchyConfig {
+    /// Side length in fine cells of the block each coarse cell covers.
+    pub block_size: usize,
+    pub biomes: Vec<MacroTile>,
+}
+
+/// Coarse layouts tried before giving up when the fine level cannot be
+/// solved inside them.
+const COARSE_ATTEMPTS: u64 = 8;
+
+/// Output of `HierarchyConfig::generate`.
+pub struct HierarchicalMap {
+    /// The solved coarse grid, one macro tile index per block.
+    pub coarse: LabelMap,
+    pub map: Map,
+    /// Side length in fine cells of the block each coarse cell covers.
+    pub block_size: usize,
+}
+
+impl HierarchicalMap {
+    /// Index of the macro tile whose block contains the fine cell `coord`.
+    pub fn macro_tile_at(&self, coord: Coord) -> usize {
+        self.coarse.get(Coord::new(
+            coord.row / self.block_size,
+            coord.col / self.block_size,
+        ))
+    }
+}
+
+impl HierarchyConfig {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let config: Self = serde_json::from_reader(BufReader::new(file))?;
+
+        if config.block_size == 0 {
+            bail!("block_size must be at least 1");
+        }
+        // Catches unknown neighbours and an empty biome list up front
+        config.macro_rules()?;
+        Ok(config)
+    }
+
+    /// Solves the coarse grid with the macro tiles, then solves a
+    /// `width` x `height` map whose blocks only allow the fine tiles of the
+    /// macro tile chosen for them. If the fine level has no solution inside
+    /// a coarse layout, a new coarse layout is tried.
+    pub fn generate(
+        &self,
+        width: usize,
+        height: usize,
+        tile_data: TileData,
+        seed: u64,
+    ) -> Result<HierarchicalMap> {
+        let rules = self.macro_rules()?;
+        let coarse_width = width.div_ceil(self.block_size);
+        let coarse_height = height.div_ceil(self.block_size);
+        let biomes: Vec<Biome> = self
+            .biomes
+            .iter()
+            .map(|biome| Biome::new(biome.name.clone(), Domain::from_tiles(&biome.tiles)))
+            .collect();
+
+        for attempt in 0..COARSE_ATTEMPTS {
+            let coarse_seed = seed.wrapping_add(attempt << 48);
+            let coarse = rules.solve(
+                coarse_width,
+                coarse_height,
+                vec![LabelSet::all(rules.len()); coarse_width * coarse_height],
+                coarse_seed,
+            )?;
+
+            let block_labels = (0..width * height)
+                .map(|index| {
+                    let coord = Coord::from_index(index, width);
+                    coarse.get(Coord::new(
+                        coord.row / self.block_size,
+                        coord.col / self.block_size,
+                    ))
+                })
+                .collect();
+            let mask = BiomeMask::from_labels(width, height, biomes.clone(), block_labels)?;
+
+            let fine = Map::with_biomes(tile_data.clone(), &mask)?;
+            match labels::solve(fine, coarse_seed.wrapping_add(1)) {
+                Ok(map) => {
+                    return Ok(HierarchicalMap {
+                        coarse,
+                        map,
+                        block_size: self.block_size,
+                    });
+                }
+                Err(e) if labels::unsolvable(&e) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        bail!(
+            "No coarse layout could be refined after {} attempts",
+            COARSE_ATTEMPTS
+        )
+    }
+
+    fn macro_rules(&self) -> Result<LabelRules> {
+        LabelRules::new(self.biomes.iter().map(|biome| {
+            (
+                biome.name.as_str(),
+                biome.neighbours.as_slice(),
+                biome.weight,
+            )
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fine_tiles_follow_their_blocks() {
+        let config = HierarchyConfig::load("assets/hierarchy.json").unwrap();
+        let tile_data = TileData::load("assets/tiledata.json").unwrap();
+        let result = config.generate(28, 20, tile_data, 4).unwrap();
+
+        assert_eq!((result.coarse.width, result.coarse.height), (4, 3));
+        for (coord, tile) in result.map.iter() {
+            let biome = &config.biomes[result.macro_tile_at(coord)];
+            let tile_type = tile.tile_type().expect("fine level should be solved");
+            assert!(
+                biome.tiles.contains(&tile_type),
+                "{:?} in a {} block",
+                tile_type,
+                biome.name
+            );
+        }
+    }
+
+    fn macro_tile(name: &str, tiles: &[TileType], neighbours: &[&str]) -> MacroTile {
+        MacroTile {
+            name: String::from(name),
+            tiles: tiles.to_vec(),
+            neighbours: neighbours.iter().map(|&name| String::from(name)).collect(),
+            weight: 1.0,
+        }
+    }
+
+    #[test]
+    fn test_more_macro_tiles_than_tile_types() {
+        let names: Vec<String> = (0..40).map(|index| format!("plains{}", index)).collect();
+        let all: Vec<&str> = names.iter().map(String::as_str).collect();
+        let config = HierarchyConfig {
+            block_size: 2,
+            biomes: names
+                .iter()
+                .map(|name| macro_tile(name, &[TileType::Grass], &all))
+                .collect(),
+        };
+        let tile_data = TileData::load("assets/tiledata.json").unwrap();
+        let result = config.generate(16, 16, tile_data, 0).unwrap();
+
+        assert!(result.coarse.labels().iter().all(|&label| label < 40));
+        assert!(
+            result
+                .coarse
+                .labels()
+                .iter()
+                .any(|&label| label >= TileType::ALL.len())
+        );
+    }
+
+    #[test]
+    fn test_unrefinable_coarse_layout_is_retried() {
+        // Deep water can never touch snow, so any layout where the two
+        // blocks differ has no fine solution
+        let config = HierarchyConfig {
+            block_size: 4,
+            biomes: vec![
+                macro_tile("sea", &[TileType::DeepWater], &["sea", "peaks"]),
+                macro_tile("peaks", &[TileType::Snow], &["peaks"]),
+            ],
+        };
+        let tile_data = TileData::load("assets/tiledata.json").unwrap();
+        let result = config.generate(8, 4, tile_data, 0).unwrap();
+
+        let coarse = result.coarse.labels();
+        assert_eq!(coarse[0], coarse[1]);
+    }
+
+    #[test]
+    fn test_unknown_neighbour_is_rejected() {
+        let mut config = HierarchyConfig::load("assets/hierarchy.json").unwrap();
+        config.biomes[0].neighbours.push(String::from("volcano"));
+        assert!(config.macro_rules().is_err());
+    }
+}
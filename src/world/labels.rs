@@ -0,0 +1,307 @@
+use crate::bucket_queue::BucketQueue;
+use crate::grid::{Coord, Map, pick_weighted};
+use crate::wfc::{Contradiction, SolveError, WFCState};
+use anyhow::{Context, Result, bail};
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use std::collections::HashMap;
+
+/// Seeded attempts per solve before giving up.
+const MAX_ATTEMPTS: u64 = 16;
+
+/// Backtracks allowed per attempt before reseeding.
+const BACKTRACK_LIMIT: usize = 500;
+
+/// A set of labels, such as the macro tiles a coarse cell may still become.
+/// Label `i` is bit `i`, so a level can have any number of labels.
+#[derive(Debug, Clone, Default)]
+pub(super) struct LabelSet(Vec<u64>);
+
+impl LabelSet {
+    pub(super) fn empty() -> Self {
+        Self(Vec::new())
+    }
+
+    pub(super) fn all(count: usize) -> Self {
+        let mut set = Self::empty();
+        for label in 0..count {
+            set.insert(label);
+        }
+        set
+    }
+
+    pub(super) fn single(label: usize) -> Self {
+        let mut set = Self::empty();
+        set.insert(label);
+        set
+    }
+
+    pub(super) fn insert(&mut self, label: usize) {
+        let word = label / 64;
+        if self.0.len() <= word {
+            self.0.resize(word + 1, 0);
+        }
+        self.0[word] |= 1 << (label % 64);
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.0.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    pub(super) fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().enumerate().flat_map(|(index, &word)| {
+            (0..64)
+                .filter(move |bit| word & (1 << bit) != 0)
+                .map(move |bit| index * 64 + bit)
+        })
+    }
+
+    fn union_with(&mut self, other: &Self) {
+        if self.0.len() < other.0.len() {
+            self.0.resize(other.0.len(), 0);
+        }
+        for (word, &other) in self.0.iter_mut().zip(&other.0) {
+            *word |= other;
+        }
+    }
+
+    /// Keeps only the labels also in `other`. Returns whether any were removed.
+    fn intersect_with(&mut self, other: &Self) -> bool {
+        let mut changed = false;
+        for (index, word) in self.0.iter_mut().enumerate() {
+            let kept = *word & other.0.get(index).copied().unwrap_or(0);
+            changed |= kept != *word;
+            *word = kept;
+        }
+        changed
+    }
+}
+
+/// A solved grid of labels, such as the coarse level of a hierarchical map.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LabelMap {
+    pub width: usize,
+    pub height: usize,
+    labels: Vec<usize>,
+}
+
+impl LabelMap {
+    pub fn get(&self, coord: Coord) -> usize {
+        self.labels[coord.to_index(self.width)]
+    }
+
+    /// Label of every cell, row by row.
+    pub fn labels(&self) -> &[usize] {
+        &self.labels
+    }
+}
+
+/// Adjacency rules and weights for a level of named labels. Labels sit next
+/// to each other the same way in every direction, so a level is solved with
+/// its own small solver rather than as a tileset.
+#[derive(Debug, Clone)]
+pub(super) struct LabelRules {
+    weights: Vec<f32>,
+    /// Labels allowed next to each label.
+    neighbours: Vec<LabelSet>,
+}
+
+impl LabelRules {
+    /// Builds the rules from each label's name, the names it may sit next to
+    /// and its weight. Adjacency is symmetric, so listing a pair on either
+    /// side is enough. Weights are clamped to `0..=f32::MAX`, NaN counting as
+    /// zero.
+    pub(super) fn new<'a>(
+        labels: impl IntoIterator<Item = (&'a str, &'a [String], f32)>,
+    ) -> Result<Self> {
+        let labels: Vec<_> = labels.into_iter().collect();
+        if labels.is_empty() {
+            bail!("Expected at least one label");
+        }
+
+        let indices: HashMap<&str, usize> = labels
+            .iter()
+            .enumerate()
+            .map(|(label, &(name, _, _))| (name, label))
+            .collect();
+
+        let mut neighbours = vec![LabelSet::empty(); labels.len()];
+        for (label, &(name, names, _)) in labels.iter().enumerate() {
+            for neighbour_name in names {
+                let &neighbour = indices.get(neighbour_name.as_str()).with_context(|| {
+                    format!("{} lists unknown neighbour {}", name, neighbour_name)
+                })?;
+                neighbours[label].insert(neighbour);
+                neighbours[neighbour].insert(label);
+            }
+        }
+
+        let weights = labels
+            .iter()
+            .map(|&(_, _, weight)| {
+                let weight = if weight.is_nan() { 0.0 } else { weight };
+                weight.clamp(0.0, f32::MAX)
+            })
+            .collect();
+
+        Ok(Self {
+            weights,
+            neighbours,
+        })
+    }
+
+    pub(super) fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// Solves a `width` x `height` grid where each cell starts with the
+    /// labels in `domains`, row by row. A contradiction restarts the solve
+    /// with a new seed rather than backtracking.
+    pub(super) fn solve(
+        &self,
+        width: usize,
+        height: usize,
+        domains: Vec<LabelSet>,
+        seed: u64,
+    ) -> Result<LabelMap> {
+        if domains.len() != width * height {
+            bail!(
+                "Expected {} label domains, found {}",
+                width * height,
+                domains.len()
+            );
+        }
+        if let Some(index) = domains.iter().position(|domain| domain.len() == 0) {
+            bail!(
+                "No label is allowed at {:?}",
+                Coord::from_index(index, width)
+            );
+        }
+
+        for attempt in 0..MAX_ATTEMPTS {
+            let mut rng = ChaCha8Rng::seed_from_u64(seed.wrapping_add(attempt << 32));
+            if let Some(labels) = self.attempt(width, height, domains.clone(), &mut rng)? {
+                return Ok(LabelMap {
+                    width,
+                    height,
+                    labels,
+                });
+            }
+        }
+
+        bail!("Failed to solve the labels after {} attempts", MAX_ATTEMPTS)
+    }
+
+    /// Collapses the lowest-entropy cell until every cell has one label.
+    /// Returns `None` on a contradiction.
+    fn attempt(
+        &self,
+        width: usize,
+        height: usize,
+        mut domains: Vec<LabelSet>,
+        rng: &mut impl Rng,
+    ) -> Result<Option<Vec<usize>>> {
+        let mut queue = BucketQueue::new(self.len(), width, height);
+        for (index, domain) in domains.iter().enumerate() {
+            if domain.len() > 1 {
+                queue.insert(Coord::from_index(index, width), domain.len())?;
+            }
+        }
+
+        let all = (0..width * height)
+            .map(|index| Coord::from_index(index, width))
+            .collect();
+        if !self.propagate(width, height, &mut domains, &mut queue, all)? {
+            return Ok(None);
+        }
+
+        while let Some((coord, _)) = queue.extract_min() {
+            let index = coord.to_index(width);
+            domains[index] = LabelSet::single(self.choose(&domains[index], rng));
+            if !self.propagate(width, height, &mut domains, &mut queue, vec![coord])? {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(
+            domains
+                .iter()
+                .map(|domain| domain.iter().next().expect("every cell has a label"))
+                .collect(),
+        ))
+    }
+
+    /// Removes labels no neighbour can sit next to, starting from `pending`.
+    /// Returns `false` if a domain becomes empty.
+    fn propagate(
+        &self,
+        width: usize,
+        height: usize,
+        domains: &mut [LabelSet],
+        queue: &mut BucketQueue,
+        mut pending: Vec<Coord>,
+    ) -> Result<bool> {
+        while let Some(coord) = pending.pop() {
+            let mut allowed = LabelSet::empty();
+            for label in domains[coord.to_index(width)].iter() {
+                allowed.union_with(&self.neighbours[label]);
+            }
+
+            for (_, neighbour) in coord.neighbours(height, width).into_iter().flatten() {
+                let domain = &mut domains[neighbour.to_index(width)];
+                if !domain.intersect_with(&allowed) {
+                    continue;
+                }
+                match domain.len() {
+                    0 => return Ok(false),
+                    1 => queue.remove(neighbour)?,
+                    entropy => queue.update_entropy(neighbour, entropy)?,
+                }
+                pending.push(neighbour);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Picks a label from `domain` with probability proportional to its
+    /// weight, or uniformly when the weights add up to nothing.
+    fn choose(&self, domain: &LabelSet, rng: &mut impl Rng) -> usize {
+        let weighted = || domain.iter().map(|label| (label, self.weights[label]));
+        pick_weighted(weighted, rng).unwrap_or_else(|| {
+            let pick = rng.random_range(0..domain.len());
+            domain.iter().nth(pick).expect("domain is not empty")
+        })
+    }
+}
+
+/// Solves `map`, reseeding when an attempt thrashes.
+pub(super) fn solve(map: Map, seed: u64) -> Result<Map> {
+    for attempt in 0..MAX_ATTEMPTS {
+        let mut state = WFCState::with_seed(map.clone(), seed.wrapping_add(attempt << 32));
+        state.set_backtrack_limit(Some(BACKTRACK_LIMIT));
+        state.propagate_all()?;
+
+        match state.solve() {
+            Ok(()) => return Ok(state.into_map()),
+            Err(e)
+                if matches!(
+                    e.downcast_ref(),
+                    Some(SolveError::BacktrackLimitReached { .. })
+                ) =>
+            {
+                continue;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(anyhow::Error::new(SolveError::BacktrackLimitReached {
+        limit: BACKTRACK_LIMIT,
+    })
+    .context(format!("Failed to solve after {} attempts", MAX_ATTEMPTS)))
+}
+
+/// Whether `error` means a map has no solution, as opposed to bad input.
+pub(super) fn unsolvable(error: &anyhow::Error) -> bool {
+    error.is::<SolveError>() || error.is::<Contradiction>()
+}
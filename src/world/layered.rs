@@ -0,0 +1,199 @@
+use super::labels;
+use crate::grid::{
+    Domain, Map, TileConstraints, TileData, TileType, TileWeights, VerticalRules, default_weight,
+};
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// A tile of the decoration layer, such as a tree or a house.
+#[derive(Deserialize, Debug, Clone)]
+pub struct Decoration {
+    pub tile: TileType,
+    /// Terrain tiles this decoration may sit on; `None` allows any.
+    #[serde(default)]
+    pub on: Option<Vec<TileType>>,
+    /// Decorations allowed next to this one in any direction.
+    pub neighbours: Vec<TileType>,
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+}
+
+impl Decoration {
+    pub fn allowed_on(&self, terrain: TileType) -> bool {
+        self.on
+            .as_ref()
+            .is_none_or(|tiles| tiles.contains(&terrain))
+    }
+}
+
+/// A layer of decorations solved on top of the terrain, as in
+/// assets/decorations.json. The decorations are tiles of their own tileset,
+/// and each cell only allows those that may sit on the terrain beneath it.
+#[derive(Deserialize, Debug, Clone)]
+pub struct DecorationLayer {
+    pub decorations: Vec<Decoration>,
+}
+
+impl DecorationLayer {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+        let layer: Self = serde_json::from_reader(BufReader::new(file))?;
+
+        layer.tile_data()?;
+        Ok(layer)
+    }
+
+    /// Tileset of the decorations. Two decorations may sit next to each
+    /// other if either lists the other as a neighbour.
+    pub fn tile_data(&self) -> Result<TileData> {
+        if self.decorations.is_empty() {
+            bail!("Expected at least one decoration");
+        }
+
+        let tiles = Domain::from_tiles(
+            &self
+                .decorations
+                .iter()
+                .map(|decoration| decoration.tile)
+                .collect::<Vec<_>>(),
+        );
+        if tiles.entropy() as usize != self.decorations.len() {
+            bail!("Each decoration tile may only be listed once");
+        }
+
+        let mut neighbours: HashMap<TileType, Domain> = HashMap::new();
+        for decoration in &self.decorations {
+            for &neighbour in &decoration.neighbours {
+                if tiles.intersection(neighbour.mask()).is_empty() {
+                    bail!(
+                        "{} lists {}, which is not a decoration",
+                        decoration.tile.name(),
+                        neighbour.name()
+                    );
+                }
+                neighbours
+                    .entry(decoration.tile)
+                    .or_insert_with(Domain::empty)
+                    .add_tiles(neighbour.mask());
+                neighbours
+                    .entry(neighbour)
+                    .or_insert_with(Domain::empty)
+                    .add_tiles(decoration.tile.mask());
+            }
+        }
+
+        let supports = tiles
+            .iter_tiles()
+            .map(|tile_type| {
+                let allowed = neighbours
+                    .get(&tile_type)
+                    .copied()
+                    .unwrap_or_else(Domain::empty);
+                let constraints = TileConstraints {
+                    top: allowed,
+                    right: allowed,
+                    bottom: allowed,
+                    left: allowed,
+                };
+                (tile_type, constraints)
+            })
+            .collect();
+
+        Ok(TileData {
+            tiles,
+            supports,
+            big_tiles: Vec::new(),
+            variants: HashMap::new(),
+            mirrored: HashMap::new(),
+            rotated: HashMap::new(),
+        })
+    }
+
+    /// Decorations allowed on each tile of `terrain`.
+    pub fn vertical_rules(&self, terrain: &TileData) -> VerticalRules {
+        let mut rules = VerticalRules::new();
+        for terrain_type in terrain.tiles.iter_tiles() {
+            for decoration in &self.decorations {
+                if decoration.allowed_on(terrain_type) {
+                    rules.allow(terrain_type, decoration.tile.mask());
+                }
+            }
+        }
+        rules
+    }
+
+    /// Solves `terrain`, then the decoration layer on top of it.
+    pub fn generate(&self, terrain: Map, seed: u64) -> Result<Map> {
+        let terrain = labels::solve(terrain, seed)?;
+        self.decorate(terrain, seed.wrapping_add(1))
+    }
+
+    /// Solves the decoration layer over an already solved terrain map. The
+    /// terrain stays reachable through `Map::beneath` of the result.
+    pub fn decorate(&self, terrain: Map, seed: u64) -> Result<Map> {
+        let rules = self.vertical_rules(&terrain.tile_data);
+        let mut layer = terrain.layer_above(self.tile_data()?, rules)?;
+
+        let mut weights = TileWeights::uniform();
+        for decoration in &self.decorations {
+            weights.set(decoration.tile, decoration.weight);
+        }
+        let table = layer.add_weight_table(weights);
+        for index in 0..layer.width * layer.height {
+            let coord = layer.coord(index);
+            layer.set_weight_table(coord, table)?;
+        }
+
+        labels::solve(layer, seed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decorations_fit_terrain_and_each_other() {
+        let layer = DecorationLayer::load("assets/decorations.json").unwrap();
+        let terrain = Map::new(16, 16).expect("tile data should load");
+        let decorated = layer.generate(terrain, 3).unwrap();
+        let terrain = decorated.beneath().expect("terrain lies beneath");
+
+        let decoration_at = |coord| {
+            let tile_type = decorated.get_tile(coord).tile_type().unwrap();
+            layer
+                .decorations
+                .iter()
+                .find(|decoration| decoration.tile == tile_type)
+                .unwrap()
+        };
+        for (coord, tile) in terrain.iter() {
+            let decoration = decoration_at(coord);
+            assert!(decoration.allowed_on(tile.tile_type().unwrap()));
+
+            for (_, neighbour) in coord.neighbours(16, 16).into_iter().flatten() {
+                let other = decoration_at(neighbour);
+                assert!(
+                    decoration.neighbours.contains(&other.tile)
+                        || other.neighbours.contains(&decoration.tile),
+                    "{} next to {}",
+                    decoration.tile.name(),
+                    other.tile.name()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_unknown_neighbour_is_rejected() {
+        let mut layer = DecorationLayer::load("assets/decorations.json").unwrap();
+        layer.decorations[0].neighbours.push(TileType::Grass);
+        assert!(layer.tile_data().is_err());
+    }
+}